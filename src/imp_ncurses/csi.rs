@@ -0,0 +1,183 @@
+// The longest sequence we are willing to buffer. Anything longer is almost certainly not a
+// terminal report, so we give up and let the bytes through as normal input.
+const MAX_LENGTH: usize = 64;
+
+/// A CSI (`ESC [`) control sequence that ncurses did not recognize as a key, split into its
+/// syntactic parts.
+pub struct ControlSequence {
+    /// One of `<`, `=`, `>`, or `?` if the parameters started with a private marker.
+    pub private_marker: Option<u8>,
+    /// The numeric parameters, separated by `;`. Empty parameters are reported as 0.
+    pub parameters: Vec<u32>,
+    /// The subparameters following each parameter, separated by `:`, e.g. `[[65, 0], []]` for
+    /// `97:65:;2`. Empty subparameters are also reported as 0.
+    pub subparameters: Vec<Vec<u32>>,
+    pub intermediates: Vec<u8>,
+    pub final_byte: u8
+}
+
+impl ControlSequence {
+    pub fn has_subparameters(&self) -> bool {
+        self.subparameters.iter().any(|subparameters| !subparameters.is_empty())
+    }
+}
+
+// Split a complete parameter into its value and subparameters
+fn end_parameter(parameters: &mut Vec<u32>, subparameters: &mut Vec<Vec<u32>>, values: &mut Vec<u32>) {
+    let mut values = std::mem::take(values);
+    parameters.push(values.remove(0));
+    subparameters.push(values);
+}
+
+/// Parse the remainder of a control sequence after the `ESC [` that introduced it, pulling bytes
+/// from `next` until the final byte. Returns `None` if the input ends early or isn't a well-formed
+/// sequence.
+pub fn parse<F: FnMut() -> Option<u8>>(mut next: F) -> Option<ControlSequence> {
+    let mut private_marker = None;
+    let mut parameters = Vec::new();
+    let mut subparameters = Vec::new();
+    let mut intermediates = Vec::new();
+    // The parameter being read, as the values before each `:` so far and the value in progress
+    let mut current_values = Vec::new();
+    let mut current_value: Option<u32> = None;
+
+    for index in 0..MAX_LENGTH {
+        let byte = next()?;
+        match byte {
+            b'<'..=b'?' if index == 0 => private_marker = Some(byte),
+            b'0'..=b'9' if intermediates.is_empty() => {
+                let digit = (byte - b'0') as u32;
+                current_value = Some(current_value.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+            },
+            b':' if intermediates.is_empty() => {
+                current_values.push(current_value.take().unwrap_or(0));
+            },
+            b';' if intermediates.is_empty() => {
+                current_values.push(current_value.take().unwrap_or(0));
+                end_parameter(&mut parameters, &mut subparameters, &mut current_values);
+            },
+            0x20..=0x2f => intermediates.push(byte),
+            0x40..=0x7e => {
+                if current_value.is_some() || !current_values.is_empty() || !parameters.is_empty() {
+                    current_values.push(current_value.unwrap_or(0));
+                    end_parameter(&mut parameters, &mut subparameters, &mut current_values);
+                }
+                return Some(ControlSequence {
+                    private_marker,
+                    parameters,
+                    subparameters,
+                    intermediates,
+                    final_byte: byte
+                });
+            },
+            // Misplaced parameter bytes and control characters
+            _ => return None
+        }
+    }
+
+    None
+}
+
+/// Consume the rest of a control sequence without interpreting it, accepting anything that is
+/// syntactically allowed, including subparameters. Returns `None` in the same cases as `parse`.
+pub fn skip<F: FnMut() -> Option<u8>>(mut next: F) -> Option<()> {
+    for _ in 0..MAX_LENGTH {
+        match next()? {
+            0x20..=0x3f => { },
+            0x40..=0x7e => return Some(()),
+            _ => return None
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, skip, ControlSequence, MAX_LENGTH};
+
+    fn parse_bytes(bytes: &[u8]) -> Option<ControlSequence> {
+        let mut bytes = bytes.iter().cloned();
+        parse(|| bytes.next())
+    }
+
+    #[test]
+    fn parameters() {
+        let sequence = parse_bytes(b"1;5A").unwrap();
+        assert_eq!(sequence.private_marker, None);
+        assert_eq!(sequence.parameters, [1, 5]);
+        assert!(!sequence.has_subparameters());
+        assert_eq!(sequence.intermediates, b"");
+        assert_eq!(sequence.final_byte, b'A');
+
+        let sequence = parse_bytes(b"A").unwrap();
+        assert!(sequence.parameters.is_empty());
+        assert_eq!(sequence.final_byte, b'A');
+
+        assert_eq!(parse_bytes(b";5A").unwrap().parameters, [0, 5]);
+        assert_eq!(parse_bytes(b"1;A").unwrap().parameters, [1, 0]);
+        assert_eq!(parse_bytes(b"48;24;80;480;800t").unwrap().parameters, [48, 24, 80, 480, 800]);
+    }
+
+    #[test]
+    fn subparameters() {
+        let sequence = parse_bytes(b"97:65;2u").unwrap();
+        assert_eq!(sequence.parameters, [97, 2]);
+        assert_eq!(sequence.subparameters, [vec![65], vec![]]);
+        assert!(sequence.has_subparameters());
+
+        let sequence = parse_bytes(b"1089::99;5:3u").unwrap();
+        assert_eq!(sequence.parameters, [1089, 5]);
+        assert_eq!(sequence.subparameters, [vec![0, 99], vec![3]]);
+    }
+
+    #[test]
+    fn private_marker_and_intermediates() {
+        let sequence = parse_bytes(b"?997;1n").unwrap();
+        assert_eq!(sequence.private_marker, Some(b'?'));
+        assert_eq!(sequence.parameters, [997, 1]);
+
+        let sequence = parse_bytes(b"<0;5;3M").unwrap();
+        assert_eq!(sequence.private_marker, Some(b'<'));
+        assert_eq!(sequence.parameters, [0, 5, 3]);
+
+        let sequence = parse_bytes(b"2 q").unwrap();
+        assert_eq!(sequence.parameters, [2]);
+        assert_eq!(sequence.intermediates, b" ");
+        assert_eq!(sequence.final_byte, b'q');
+    }
+
+    #[test]
+    fn malformed() {
+        // Truncated
+        assert!(parse_bytes(b"1;5").is_none());
+        // A private marker after the start, or a parameter after an intermediate
+        assert!(parse_bytes(b"1?A").is_none());
+        assert!(parse_bytes(b" 1q").is_none());
+        // A control character
+        assert!(parse_bytes(b"1\x1bA").is_none());
+        // Too large to fit
+        assert!(parse_bytes(b"99999999999A").is_none());
+    }
+
+    #[test]
+    fn max_length() {
+        let mut longest = vec![b';'; MAX_LENGTH - 1];
+        longest.push(b'A');
+        assert_eq!(parse_bytes(&longest).unwrap().parameters.len(), MAX_LENGTH);
+
+        let mut too_long = vec![b';'; MAX_LENGTH];
+        too_long.push(b'A');
+        assert!(parse_bytes(&too_long).is_none());
+    }
+
+    #[test]
+    fn skip_anything_well_formed() {
+        let mut bytes = b"?1;5:3$ A".iter().cloned();
+        assert_eq!(skip(|| bytes.next()), Some(()));
+        assert_eq!(bytes.next(), None);
+
+        let mut bytes = b"1\x1b".iter().cloned();
+        assert_eq!(skip(|| bytes.next()), None);
+    }
+}
@@ -0,0 +1,79 @@
+use super::{csi, osc};
+
+/// A DCS (`ESC P`) device control string, as sent by the terminal in response to a query.
+pub struct DeviceControlString {
+    /// The parameters, intermediates, and final byte, which share the syntax of a CSI sequence.
+    pub header: csi::ControlSequence,
+    pub data: Vec<u8>,
+    /// Whether the data was longer than allowed, in which case the excess was dropped.
+    pub truncated: bool
+}
+
+/// Parse the remainder of a device control string after the `ESC P` that introduced it, keeping
+/// at most `max_length` bytes of data. Returns `None` if the input ends early or isn't a
+/// well-formed string.
+pub fn parse<F: FnMut() -> Option<u8>>(mut next: F, max_length: usize) -> Option<DeviceControlString> {
+    let header = csi::parse(&mut next)?;
+    let (data, truncated) = osc::parse_string(next, max_length)?;
+    Some(DeviceControlString { header, data, truncated })
+}
+
+/// Decode a string of hexadecimal digit pairs, as used by XTGETTCAP.
+pub fn decode_hex(encoded: &[u8]) -> Option<Vec<u8>> {
+    let pairs = encoded.chunks_exact(2);
+    if !pairs.remainder().is_empty() {
+        return None;
+    }
+    pairs.map(|pair| {
+        let high = (pair[0] as char).to_digit(16)?;
+        let low = (pair[1] as char).to_digit(16)?;
+        Some((high * 16 + low) as u8)
+    }).collect()
+}
+
+/// Encode a string as hexadecimal digit pairs, as used by XTGETTCAP.
+pub fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_hex, encode_hex, parse};
+
+    #[test]
+    fn hex() {
+        assert_eq!(encode_hex(b"kUP5"), "6B555035");
+        assert_eq!(decode_hex(b"6B555035"), Some(b"kUP5".to_vec()));
+        assert_eq!(decode_hex(b"6b555035"), Some(b"kUP5".to_vec()));
+        assert_eq!(decode_hex(b""), Some(Vec::new()));
+        assert_eq!(decode_hex(b"6B5"), None);
+        assert_eq!(decode_hex(b"6G"), None);
+    }
+
+    #[test]
+    fn reply() {
+        let mut bytes = b"1+r6B555035=1B5B313B3541\x1b\\".iter().cloned();
+        let string = parse(|| bytes.next(), 64).unwrap();
+        assert_eq!(string.header.parameters, [1]);
+        assert_eq!(string.header.intermediates, b"+");
+        assert_eq!(string.header.final_byte, b'r');
+        assert_eq!(string.data, b"6B555035=1B5B313B3541");
+        assert!(!string.truncated);
+    }
+
+    #[test]
+    fn truncated_reply() {
+        let mut bytes = b"1+r6B555035\x07".iter().cloned();
+        let string = parse(|| bytes.next(), 4).unwrap();
+        assert_eq!(string.data, b"6B55");
+        assert!(string.truncated);
+    }
+
+    #[test]
+    fn malformed_reply() {
+        let mut bytes = b"1+r6B55".iter().cloned();
+        assert!(parse(|| bytes.next(), 64).is_none());
+        let mut bytes = b"1+r6B55\x1bx".iter().cloned();
+        assert!(parse(|| bytes.next(), 64).is_none());
+    }
+}
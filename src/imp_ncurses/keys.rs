@@ -0,0 +1,682 @@
+use crate::{ControlCharacterStyle, Event, KeyInput, Modifiers};
+use super::{csi, kitty};
+
+// Terminfo names of keys that xterm-like terminals also describe with modifiers, by appending the
+// xterm modifier parameter (e.g. kLFT5 for Ctrl+Left). Without a suffix, the name means Shift.
+const MODIFIABLE_KEYS: &[(&str, i32)] = &[
+    ("kBEG", ncurses::KEY_BEG),
+    ("kDC", ncurses::KEY_DC),
+    ("kDN", ncurses::KEY_DOWN),
+    ("kEND", ncurses::KEY_END),
+    ("kHOM", ncurses::KEY_HOME),
+    ("kIC", ncurses::KEY_IC),
+    ("kLFT", ncurses::KEY_LEFT),
+    ("kNXT", ncurses::KEY_NPAGE),
+    ("kPRV", ncurses::KEY_PPAGE),
+    ("kRIT", ncurses::KEY_RIGHT),
+    ("kUP", ncurses::KEY_UP),
+];
+
+// Extended terminfo names of keypad keys, which only take the modifier suffix. The keys named by
+// position are the ones that the standard names (ka1, kb2, ...) leave out.
+const EXTENDED_KEYPAD_KEYS: &[(&str, char)] = &[
+    ("kpADD", '+'),
+    ("kpCMA", ','),
+    ("kpDIV", '/'),
+    ("kpDOT", '.'),
+    ("kpMUL", '*'),
+    ("kpSUB", '-'),
+    ("kpZRO", '0'),
+    ("ka2", '8'),
+    ("kb1", '4'),
+    ("kb3", '6'),
+    ("kc2", '2'),
+];
+
+// Terminfo names of the standard keys, and the ncurses key codes that we report them as. The
+// function keys kf0 through kf63 are handled separately.
+const STANDARD_KEYS: &[(&str, i32)] = &[
+    ("kbs", ncurses::KEY_BACKSPACE),
+    ("kcud1", ncurses::KEY_DOWN),
+    ("kcuu1", ncurses::KEY_UP),
+    ("kcub1", ncurses::KEY_LEFT),
+    ("kcuf1", ncurses::KEY_RIGHT),
+    ("khome", ncurses::KEY_HOME),
+    ("kdl1", ncurses::KEY_DL),
+    ("kil1", ncurses::KEY_IL),
+    ("kdch1", ncurses::KEY_DC),
+    ("kich1", ncurses::KEY_IC),
+    ("krmir", ncurses::KEY_EIC),
+    ("kclr", ncurses::KEY_CLEAR),
+    ("ked", ncurses::KEY_EOS),
+    ("kel", ncurses::KEY_EOL),
+    ("kind", ncurses::KEY_SF),
+    ("kri", ncurses::KEY_SR),
+    ("knp", ncurses::KEY_NPAGE),
+    ("kpp", ncurses::KEY_PPAGE),
+    ("khts", ncurses::KEY_STAB),
+    ("kctab", ncurses::KEY_CTAB),
+    ("ktbc", ncurses::KEY_CATAB),
+    ("kent", ncurses::KEY_ENTER),
+    ("kprt", ncurses::KEY_PRINT),
+    ("kll", ncurses::KEY_LL),
+    ("ka1", ncurses::KEY_A1),
+    ("ka3", ncurses::KEY_A3),
+    ("kb2", ncurses::KEY_B2),
+    ("kc1", ncurses::KEY_C1),
+    ("kc3", ncurses::KEY_C3),
+    ("kcbt", ncurses::KEY_BTAB),
+    ("kbeg", ncurses::KEY_BEG),
+    ("kcan", ncurses::KEY_CANCEL),
+    ("kclo", ncurses::KEY_CLOSE),
+    ("kcmd", ncurses::KEY_COMMAND),
+    ("kcpy", ncurses::KEY_COPY),
+    ("kcrt", ncurses::KEY_CREATE),
+    ("kend", ncurses::KEY_END),
+    ("kext", ncurses::KEY_EXIT),
+    ("kfnd", ncurses::KEY_FIND),
+    ("khlp", ncurses::KEY_HELP),
+    ("kmrk", ncurses::KEY_MARK),
+    ("kmsg", ncurses::KEY_MESSAGE),
+    ("kmov", ncurses::KEY_MOVE),
+    ("knxt", ncurses::KEY_NEXT),
+    ("kopn", ncurses::KEY_OPEN),
+    ("kopt", ncurses::KEY_OPTIONS),
+    ("kprv", ncurses::KEY_PREVIOUS),
+    ("kred", ncurses::KEY_REDO),
+    ("kref", ncurses::KEY_REFERENCE),
+    ("krfr", ncurses::KEY_REFRESH),
+    ("krpl", ncurses::KEY_REPLACE),
+    ("krst", ncurses::KEY_RESTART),
+    ("kres", ncurses::KEY_RESUME),
+    ("ksav", ncurses::KEY_SAVE),
+    ("kBEG", ncurses::KEY_SBEG),
+    ("kCAN", ncurses::KEY_SCANCEL),
+    ("kCMD", ncurses::KEY_SCOMMAND),
+    ("kCPY", ncurses::KEY_SCOPY),
+    ("kCRT", ncurses::KEY_SCREATE),
+    ("kDC", ncurses::KEY_SDC),
+    ("kDL", ncurses::KEY_SDL),
+    ("kslt", ncurses::KEY_SELECT),
+    ("kEND", ncurses::KEY_SEND),
+    ("kEOL", ncurses::KEY_SEOL),
+    ("kEXT", ncurses::KEY_SEXIT),
+    ("kFND", ncurses::KEY_SFIND),
+    ("kHLP", ncurses::KEY_SHELP),
+    ("kHOM", ncurses::KEY_SHOME),
+    ("kIC", ncurses::KEY_SIC),
+    ("kLFT", ncurses::KEY_SLEFT),
+    ("kMSG", ncurses::KEY_SMESSAGE),
+    ("kMOV", ncurses::KEY_SMOVE),
+    ("kNXT", ncurses::KEY_SNEXT),
+    ("kOPT", ncurses::KEY_SOPTIONS),
+    ("kPRV", ncurses::KEY_SPREVIOUS),
+    ("kPRT", ncurses::KEY_SPRINT),
+    ("kRDO", ncurses::KEY_SREDO),
+    ("kRPL", ncurses::KEY_SREPLACE),
+    ("kRIT", ncurses::KEY_SRIGHT),
+    ("kRES", ncurses::KEY_SRSUME),
+    ("kSAV", ncurses::KEY_SSAVE),
+    ("kSPD", ncurses::KEY_SSUSPEND),
+    ("kUND", ncurses::KEY_SUNDO),
+    ("kspd", ncurses::KEY_SUSPEND),
+    ("kund", ncurses::KEY_UNDO),
+];
+
+pub fn function_key(number: i32) -> KeyInput {
+    KeyInput::Special(ncurses::KEY_F0 + number)
+}
+
+// Keys sent as `CSI 1 ; m X` or `SS3 X`
+fn letter_key(final_byte: u8) -> Option<KeyInput> {
+    Some(match final_byte {
+        b'A' => KeyInput::Special(ncurses::KEY_UP),
+        b'B' => KeyInput::Special(ncurses::KEY_DOWN),
+        b'C' => KeyInput::Special(ncurses::KEY_RIGHT),
+        b'D' => KeyInput::Special(ncurses::KEY_LEFT),
+        b'E' => KeyInput::Special(ncurses::KEY_B2), // The center of the keypad
+        b'F' => KeyInput::Special(ncurses::KEY_END),
+        b'H' => KeyInput::Special(ncurses::KEY_HOME),
+        b'P' => function_key(1),
+        b'Q' => function_key(2),
+        b'R' => function_key(3),
+        b'S' => function_key(4),
+        _ => return None
+    })
+}
+
+// Keys sent as `CSI n ; m ~`, numbered as on the VT220
+fn tilde_key(number: u32) -> Option<KeyInput> {
+    Some(match number {
+        1 | 7 => KeyInput::Special(ncurses::KEY_HOME),
+        2 => KeyInput::Special(ncurses::KEY_IC),
+        3 => KeyInput::Special(ncurses::KEY_DC),
+        4 | 8 => KeyInput::Special(ncurses::KEY_END),
+        5 => KeyInput::Special(ncurses::KEY_PPAGE),
+        6 => KeyInput::Special(ncurses::KEY_NPAGE),
+        11..=15 => function_key(number as i32 - 10),
+        17..=21 => function_key(number as i32 - 11),
+        23..=26 => function_key(number as i32 - 12),
+        28..=29 => function_key(number as i32 - 13),
+        31..=34 => function_key(number as i32 - 14),
+        _ => return None
+    })
+}
+
+// Keys on the numeric keypad in application keypad mode, sent as `SS3 X`
+fn keypad_key(final_byte: u8) -> Option<KeyInput> {
+    Some(match final_byte {
+        b'j' => KeyInput::Keypad('*'),
+        b'k' => KeyInput::Keypad('+'),
+        b'l' => KeyInput::Keypad(','),
+        b'm' => KeyInput::Keypad('-'),
+        b'n' => KeyInput::Keypad('.'),
+        b'o' => KeyInput::Keypad('/'),
+        b'p'..=b'y' => KeyInput::Keypad((b'0' + final_byte - b'p') as char),
+        b'M' => KeyInput::Keypad('\n'),
+        b'X' => KeyInput::Keypad('='),
+        _ => return None
+    })
+}
+
+/// The key that a keypad key acts as, given the character it types with NumLock on and whether
+/// NumLock is on. Without NumLock, the digits and the decimal point act as editing keys.
+pub fn keypad_key_with_num_lock(character: char, num_lock: bool) -> KeyInput {
+    if num_lock {
+        return KeyInput::Keypad(character);
+    }
+    match character {
+        '0' => KeyInput::Special(ncurses::KEY_IC),
+        '1' => KeyInput::Special(ncurses::KEY_END),
+        '2' => KeyInput::Special(ncurses::KEY_DOWN),
+        '3' => KeyInput::Special(ncurses::KEY_NPAGE),
+        '4' => KeyInput::Special(ncurses::KEY_LEFT),
+        '5' => KeyInput::Special(ncurses::KEY_B2),
+        '6' => KeyInput::Special(ncurses::KEY_RIGHT),
+        '7' => KeyInput::Special(ncurses::KEY_HOME),
+        '8' => KeyInput::Special(ncurses::KEY_UP),
+        '9' => KeyInput::Special(ncurses::KEY_PPAGE),
+        '.' => KeyInput::Special(ncurses::KEY_DC),
+        _ => KeyInput::Keypad(character)
+    }
+}
+
+/// Decode a terminfo key definition that is really a keypad key in application keypad mode.
+/// Terminfo names some of these by their position instead, e.g. `ka1` for the upper left key.
+pub fn decode_keypad_definition(definition: &[u8]) -> Option<Event> {
+    match *definition {
+        [0x1b, b'O', final_byte] => {
+            Some(Event::KeyPress { modifiers: Modifiers::NONE, key: keypad_key(final_byte)?, is_repeat: false, ambiguous: None, alternates: None })
+        },
+        _ => None
+    }
+}
+
+/// Decode a PC-style function key sent as a CSI sequence, either `CSI 1 ; m X` for the cursor keys
+/// and F1-F4 or `CSI n ; m ~` for the editing keys and the other function keys, with any
+/// combination of modifiers. Kitty's `CSI n ; m u` is also decoded, as is the event type that kitty
+/// adds to the others.
+pub fn decode_csi(sequence: &csi::ControlSequence) -> Option<Event> {
+    if sequence.private_marker.is_some() || !sequence.intermediates.is_empty() {
+        return None;
+    }
+    if sequence.final_byte == b'u' {
+        return kitty::decode_csi(sequence);
+    }
+    // Kitty puts the event type after the modifiers as a subparameter, e.g. `CSI 1 ; 5 : 3 A` for
+    // releasing Ctrl+Up
+    let event_type = match sequence.subparameters[..] {
+        [ref key, ref modifiers] if key.is_empty() => match modifiers[..] {
+            [] => 1,
+            [event_type] => event_type,
+            _ => return None
+        },
+        _ if sequence.has_subparameters() => return None,
+        _ => 1
+    };
+    let (number, modifiers) = match sequence.parameters[..] {
+        // xterm's modifyOtherKeys sends other modified keys as `CSI 27 ; m ; c ~`, where c is the
+        // character that the key would normally produce
+        [27, modifiers, character] if sequence.final_byte == b'~' => {
+            let key = KeyInput::Codepoint(std::char::from_u32(character)?);
+            return Some(Event::KeyPress { modifiers: Modifiers::from_xterm_param(modifiers)?, key, is_repeat: false, ambiguous: None, alternates: None });
+        },
+        [] => (1, Modifiers::NONE),
+        [number] => (number, Modifiers::NONE),
+        [number, modifiers] => (number, Modifiers::from_xterm_param(modifiers)?),
+        _ => return None
+    };
+    let key = match (number, sequence.final_byte) {
+        // This is F16 on the VT220, but xterm sends it for the Menu key and has F16 elsewhere
+        (29, b'~') => KeyInput::Menu,
+        (_, b'~') => tilde_key(number)?,
+        (1, final_byte) => letter_key(final_byte)?,
+        _ => return None
+    };
+    kitty::key_event(modifiers, key, event_type, None)
+}
+
+/// Decode one of rxvt's editing and function keys, which are sent as `CSI n ~`, `CSI n $`,
+/// `CSI n ^`, or `CSI n @` for no modifiers, Shift, Ctrl, and Ctrl+Shift respectively. rxvt, like
+/// the Linux console, also sends Shift+F3 through Shift+F10 as F13 through F20.
+pub fn decode_rxvt(number: u32, suffix: u8) -> Option<Event> {
+    let mut modifiers = match suffix {
+        b'~' => Modifiers::NONE,
+        b'$' => Modifiers::SHIFT,
+        b'^' => Modifiers::CTRL,
+        b'@' => Modifiers::CTRL | Modifiers::SHIFT,
+        _ => return None
+    };
+    let mut key = tilde_key(number)?;
+    if let KeyInput::Special(code) = key {
+        let function = code - ncurses::KEY_F0;
+        if (suffix == b'~' || suffix == b'^') && (13..=20).contains(&function) {
+            modifiers = modifiers | Modifiers::SHIFT;
+            key = function_key(function - 10);
+        }
+    }
+    Some(Event::KeyPress { modifiers, key, is_repeat: false, ambiguous: None, alternates: None })
+}
+
+/// Decode a key sent as an SS3 (`ESC O`) sequence, which is used for the cursor keys, F1-F4, and
+/// the numeric keypad in application mode. Older versions of xterm put modifiers right after the
+/// `O`, as in `SS3 5 P`.
+pub fn decode_ss3(sequence: &csi::ControlSequence) -> Option<Event> {
+    if sequence.private_marker.is_some() || !sequence.intermediates.is_empty() || sequence.has_subparameters() {
+        return None;
+    }
+    let modifiers = match sequence.parameters[..] {
+        [] => Modifiers::NONE,
+        [modifiers] | [1, modifiers] => Modifiers::from_xterm_param(modifiers)?,
+        _ => return None
+    };
+    let key = letter_key(sequence.final_byte).or_else(|| keypad_key(sequence.final_byte))?;
+    Some(Event::KeyPress { modifiers, key, is_repeat: false, ambiguous: None, alternates: None })
+}
+
+/// Decode a control character, i.e. a byte below `0x20` or DEL, into the key press that it is
+/// reported as. Letters are Ctrl plus the letter, except for the ones that are keys of their own.
+///
+/// | Byte          | Key                                                      |
+/// | ------------- | -------------------------------------------------------- |
+/// | `0x00`        | Ctrl+Space, or Ctrl+2 in the `Digits` style              |
+/// | `0x01`-`0x1A` | Ctrl+A through Ctrl+Z, except for the following          |
+/// | `0x08`        | `\u{8}` itself, as it is Backspace or Ctrl+Backspace in some terminals |
+/// | `0x09`        | Tab                                                      |
+/// | `0x0A`        | Enter                                                    |
+/// | `0x0D`        | Enter                                                    |
+/// | `0x1B`        | Escape                                                   |
+/// | `0x1C`-`0x1F` | depending on the `ControlCharacterStyle`                 |
+/// | `0x7F`        | Backspace                                                |
+pub fn decode_control_character(byte: u8, style: ControlCharacterStyle) -> Option<(Modifiers, KeyInput)> {
+    use crate::ControlCharacterStyle::*;
+    const NONE: Modifiers = Modifiers::NONE;
+    const CTRL: Modifiers = Modifiers::CTRL;
+    const SHIFT: Modifiers = Modifiers::SHIFT;
+
+    let (modifiers, character) = match (byte, style) {
+        (0x00, Digits) => (CTRL, '2'),
+        (0x00, _) => (CTRL, ' '),
+        (0x08, _) => (NONE, '\u{8}'),
+        (b'\t', _) => (NONE, '\t'),
+        (b'\n', _) | (b'\r', _) => (NONE, '\n'),
+        (0x01..=0x1a, _) => (CTRL, (byte + 96) as char),
+        (0x1b, _) => (NONE, '\u{1b}'),
+        (0x1c, Digits) => (CTRL, '4'),
+        (0x1c, _) => (CTRL, '\\'),
+        (0x1d, Digits) => (CTRL, '5'),
+        (0x1d, _) => (CTRL, ']'),
+        (0x1e, Caret) => (CTRL, '^'),
+        (0x1e, UsLayout) => (CTRL | SHIFT, '6'),
+        (0x1e, Digits) => (CTRL, '6'),
+        (0x1f, Caret) => (CTRL, '_'),
+        (0x1f, UsLayout) => (CTRL, '/'),
+        (0x1f, Digits) => (CTRL, '7'),
+        (0x7f, _) => return Some((NONE, KeyInput::Special(ncurses::KEY_BACKSPACE))),
+        _ => return None
+    };
+    Some((modifiers, KeyInput::Codepoint(character)))
+}
+
+/// The key press that a single control character could also have come from, besides the one it
+/// is decoded as. Which of the two a terminal means depends on its settings, e.g. which byte it
+/// sends for Backspace.
+pub fn ambiguous_alternative(byte: u8, style: ControlCharacterStyle) -> Option<(Modifiers, KeyInput)> {
+    let key = match byte {
+        0x00 if style == ControlCharacterStyle::Digits => KeyInput::Codepoint(' '),
+        0x00 => KeyInput::Codepoint('`'),
+        0x08 => KeyInput::Codepoint('h'),
+        b'\t' => KeyInput::Codepoint('i'),
+        b'\n' => KeyInput::Codepoint('j'),
+        b'\r' => KeyInput::Codepoint('m'),
+        0x1b => KeyInput::Codepoint('['),
+        // The digit aliases are the alternative to the other styles and the other way around
+        0x1c..=0x1f if style == ControlCharacterStyle::Digits => {
+            return decode_control_character(byte, ControlCharacterStyle::Caret);
+        },
+        0x1c..=0x1f => return decode_control_character(byte, ControlCharacterStyle::Digits),
+        0x7f => KeyInput::Special(ncurses::KEY_BACKSPACE),
+        _ => return None
+    };
+    Some((Modifiers::CTRL, key))
+}
+
+/// Decode a byte that was sent with Alt by setting its high bit, given the byte without it. Control
+/// characters are decoded the same way as after an escape.
+pub fn decode_meta(byte: u8, style: ControlCharacterStyle) -> Event {
+    const ALT: Modifiers = Modifiers::ALT;
+    let (modifiers, key, ambiguous) = if byte == b'\n' {
+        // Alt+Enter sends a carriage return, so a newline must come from Ctrl+Alt+J
+        (Modifiers::CTRL, KeyInput::Codepoint('j'), None)
+    } else {
+        match decode_control_character(byte, style) {
+            Some((modifiers, key)) => (modifiers, key, ambiguous_alternative(byte, style)),
+            None => (Modifiers::NONE, KeyInput::Codepoint(byte as char), None)
+        }
+    };
+    Event::KeyPress {
+        modifiers: modifiers | ALT,
+        key,
+        is_repeat: false,
+        ambiguous: ambiguous.map(|(modifiers, key)| (modifiers | ALT, key)),
+        alternates: None
+    }
+}
+
+/// Mark a key press that was decoded from the single byte `byte` with the other key press that
+/// sends the same byte, if there is one.
+pub fn mark_ambiguous(event: Event, byte: u8, style: ControlCharacterStyle) -> Event {
+    match event {
+        Event::KeyPress { modifiers, key, is_repeat, ambiguous: None, alternates } => {
+            Event::KeyPress { modifiers, key, is_repeat, ambiguous: ambiguous_alternative(byte, style), alternates }
+        },
+        event => event
+    }
+}
+
+/// Every standard key that terminfo can describe, as its capability name and the event that its
+/// sequence should produce.
+pub fn standard_keys() -> impl Iterator<Item=(String, Event)> {
+    let function_keys = (0..=63).map(|number| (format!("kf{}", number), ncurses::KEY_F0 + number));
+    STANDARD_KEYS.iter().map(|&(name, code)| (name.to_string(), code)).chain(function_keys)
+        .map(|(name, code)| (name, decode_key_code(code)))
+}
+
+// Split the key codes that ncurses has for shifted and otherwise modified keys into a base key and
+// modifiers
+fn decode_key_code(code: i32) -> Event {
+    use crate::KeyInput::*;
+    const NONE: Modifiers = Modifiers::NONE;
+    const SHIFT: Modifiers = Modifiers::SHIFT;
+    const CTRL: Modifiers = Modifiers::CTRL;
+
+    let (modifiers, key) = match code {
+        // Shifted standard keys
+        ncurses::KEY_SLEFT => (SHIFT, Special(ncurses::KEY_LEFT)),
+        ncurses::KEY_SRIGHT => (SHIFT, Special(ncurses::KEY_RIGHT)),
+        ncurses::KEY_SR => (SHIFT, Special(ncurses::KEY_UP)),
+        ncurses::KEY_SF => (SHIFT, Special(ncurses::KEY_DOWN)),
+        ncurses::KEY_SHOME => (SHIFT, Special(ncurses::KEY_HOME)),
+        ncurses::KEY_SEND => (SHIFT, Special(ncurses::KEY_END)),
+        ncurses::KEY_SDC => (SHIFT, Special(ncurses::KEY_DC)),
+        ncurses::KEY_BTAB => (SHIFT, Codepoint('\t')),
+        ncurses::KEY_ENTER => (NONE, Keypad('\n')),
+        ncurses::KEY_PRINT => (NONE, PrintScreen),
+        // Shifted uncommon keys
+        ncurses::KEY_SBEG => (SHIFT, Special(ncurses::KEY_BEG)),
+        ncurses::KEY_SCANCEL => (SHIFT, Special(ncurses::KEY_CANCEL)),
+        ncurses::KEY_SCOMMAND => (SHIFT, Special(ncurses::KEY_COMMAND)),
+        ncurses::KEY_SCOPY => (SHIFT, Special(ncurses::KEY_COPY)),
+        ncurses::KEY_SCREATE => (SHIFT, Special(ncurses::KEY_CREATE)),
+        ncurses::KEY_SDL => (SHIFT, Special(ncurses::KEY_DL)),
+        ncurses::KEY_SEOL => (SHIFT, Special(ncurses::KEY_EOL)),
+        ncurses::KEY_SEXIT => (SHIFT, Special(ncurses::KEY_EXIT)),
+        ncurses::KEY_SFIND => (SHIFT, Special(ncurses::KEY_FIND)),
+        ncurses::KEY_SHELP => (SHIFT, Special(ncurses::KEY_HELP)),
+        ncurses::KEY_SIC => (SHIFT, Special(ncurses::KEY_IC)),
+        ncurses::KEY_SMESSAGE => (SHIFT, Special(ncurses::KEY_MESSAGE)),
+        ncurses::KEY_SMOVE => (SHIFT, Special(ncurses::KEY_MOVE)),
+        ncurses::KEY_SNEXT => (SHIFT, Special(ncurses::KEY_NEXT)),
+        ncurses::KEY_SOPTIONS => (SHIFT, Special(ncurses::KEY_OPTIONS)),
+        ncurses::KEY_SPREVIOUS => (SHIFT, Special(ncurses::KEY_PREVIOUS)),
+        ncurses::KEY_SPRINT => (SHIFT, PrintScreen),
+        ncurses::KEY_SREDO => (SHIFT, Special(ncurses::KEY_REDO)),
+        ncurses::KEY_SREPLACE => (SHIFT, Special(ncurses::KEY_REPLACE)),
+        ncurses::KEY_SRSUME => (SHIFT, Special(ncurses::KEY_RESUME)),
+        ncurses::KEY_SSAVE => (SHIFT, Special(ncurses::KEY_SAVE)),
+        ncurses::KEY_SUNDO => (SHIFT, Special(ncurses::KEY_UNDO)),
+        // Ctrl+Z triggers a suspend
+        ncurses::KEY_SUSPEND => (CTRL, Codepoint('z')),
+        ncurses::KEY_SSUSPEND => (CTRL | SHIFT, Codepoint('z')),
+        _ => (NONE, Special(code))
+    };
+    Event::KeyPress { modifiers, key, is_repeat: false, ambiguous: None, alternates: None }
+}
+
+/// All the terminfo names that follow the modified key naming convention, e.g. kUP, kUP3, ...,
+/// kUP8.
+pub fn modified_key_capability_names() -> impl Iterator<Item=String> {
+    MODIFIABLE_KEYS.iter().flat_map(|&(base, _)| {
+        std::iter::once(base.to_string()).chain((3..=8).map(move |parameter| format!("{}{}", base, parameter)))
+    })
+}
+
+/// Decode the terminfo name of a modified key, following the convention described in
+/// `modified_key_capability_names`. Besides the keys listed there, any other key can take the
+/// modifier suffix, e.g. kpADD5 for Ctrl+Keypad+ or kb23 for Alt with the center of the keypad.
+pub fn decode_capability_name(name: &[u8]) -> Option<Event> {
+    let name = std::str::from_utf8(name).ok()?;
+    let (key, parameter) = MODIFIABLE_KEYS.iter().find(|&&(base, _)| base == name).map(|&(_, key)| (KeyInput::Special(key), 2))
+        .or_else(|| (1..=2).filter_map(|suffix_length| {
+            let (base, suffix) = name.split_at(name.len().checked_sub(suffix_length)?);
+            let parameter = suffix.parse().ok().filter(|&parameter| parameter > 2 && !suffix.starts_with('0'))?;
+            Some((modifiable_key(base)?, parameter))
+        }).next())?;
+    Some(Event::KeyPress { modifiers: Modifiers::from_xterm_param(parameter)?, key, is_repeat: false, ambiguous: None, alternates: None })
+}
+
+// The key that a terminfo name describes before the modifier suffix is added. The Shift versions of
+// standard keys (kDC, kLFT, ...) aren't included, since their names are taken by `MODIFIABLE_KEYS`,
+// and neither are the function keys, which have their own convention.
+fn modifiable_key(base: &str) -> Option<KeyInput> {
+    if let Some(&(_, key)) = MODIFIABLE_KEYS.iter().find(|&&(name, _)| name == base) {
+        return Some(KeyInput::Special(key));
+    }
+    if let Some(&(_, character)) = EXTENDED_KEYPAD_KEYS.iter().find(|&&(name, _)| name == base) {
+        return Some(KeyInput::Keypad(character));
+    }
+    STANDARD_KEYS.iter()
+        .find(|&&(name, _)| name == base && !name[1..].starts_with(|c: char| c.is_ascii_uppercase()))
+        .map(|&(_, key)| KeyInput::Special(key))
+}
+
+/// Decode F13 through F63 in terminals that follow xterm's layout, where they are F1-F12 combined
+/// with Shift, Ctrl, Ctrl+Shift, Alt, and Alt+Shift in turn.
+pub fn decode_extended_function_key(number: i32) -> Option<Event> {
+    const SHIFT: Modifiers = Modifiers::SHIFT;
+    const CTRL: Modifiers = Modifiers::CTRL;
+    const ALT: Modifiers = Modifiers::ALT;
+    if !(13..=63).contains(&number) {
+        return None;
+    }
+    let modifiers = [SHIFT, CTRL, CTRL | SHIFT, ALT, ALT | SHIFT][(number as usize - 13) / 12];
+    Some(Event::KeyPress { modifiers, key: function_key((number - 1) % 12 + 1), is_repeat: false, ambiguous: None, alternates: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event::*;
+
+    fn parse(bytes: &[u8]) -> csi::ControlSequence {
+        let mut bytes = bytes.iter().cloned();
+        csi::parse(|| bytes.next()).unwrap()
+    }
+
+    #[test]
+    fn csi_cursor_keys() {
+        assert!(matches!(decode_csi(&parse(b"A")), Some(KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_csi(&parse(b"1;5A")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_csi(&parse(b"1;3C")), Some(KeyPress { modifiers: Modifiers::ALT, key: KeyInput::Special(ncurses::KEY_RIGHT), .. })));
+        assert!(matches!(decode_csi(&parse(b"1;2H")), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(ncurses::KEY_HOME), .. })));
+        assert!(matches!(decode_csi(&parse(b"1;5E")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_B2), .. })));
+    }
+
+    #[test]
+    fn csi_function_keys() {
+        assert!(matches!(decode_csi(&parse(b"1;2P")), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(code), .. }) if code == ncurses::KEY_F0 + 1));
+        assert!(matches!(decode_csi(&parse(b"15;5~")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(code), .. }) if code == ncurses::KEY_F0 + 5));
+        assert!(matches!(decode_csi(&parse(b"24;8~")), Some(KeyPress { key: KeyInput::Special(code), .. }) if code == ncurses::KEY_F0 + 12));
+        assert!(matches!(decode_csi(&parse(b"5;5~")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_PPAGE), .. })));
+        assert!(matches!(decode_csi(&parse(b"3;2~")), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(ncurses::KEY_DC), .. })));
+    }
+
+    #[test]
+    fn csi_modify_other_keys() {
+        assert!(matches!(decode_csi(&parse(b"27;5;106~")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Codepoint('j'), .. })));
+    }
+
+    #[test]
+    fn csi_not_keys() {
+        assert!(decode_csi(&parse(b"2;5A")).is_none());
+        assert!(decode_csi(&parse(b"16~")).is_none());
+        assert!(decode_csi(&parse(b"1;0A")).is_none());
+        assert!(decode_csi(&parse(b"?1;5A")).is_none());
+        assert!(decode_csi(&parse(b"1;5;1A")).is_none());
+    }
+
+    #[test]
+    fn ss3() {
+        assert!(matches!(decode_ss3(&parse(b"A")), Some(KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_ss3(&parse(b"5A")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_ss3(&parse(b"1;2P")), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(code), .. }) if code == ncurses::KEY_F0 + 1));
+        assert!(matches!(decode_ss3(&parse(b"M")), Some(KeyPress { key: KeyInput::Keypad('\n'), .. })));
+        assert!(matches!(decode_ss3(&parse(b"5k")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Keypad('+'), .. })));
+        assert!(decode_ss3(&parse(b"Z")).is_none());
+    }
+
+    fn is_function_key(key: KeyInput, number: i32) -> bool {
+        matches!(key, KeyInput::Special(code) if code == ncurses::KEY_F0 + number)
+    }
+
+    #[test]
+    fn rxvt_editing_keys() {
+        assert!(matches!(decode_rxvt(5, b'~'), Some(KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Special(ncurses::KEY_PPAGE), .. })));
+        assert!(matches!(decode_rxvt(5, b'^'), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_PPAGE), .. })));
+        assert!(matches!(decode_rxvt(3, b'$'), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(ncurses::KEY_DC), .. })));
+        assert!(matches!(decode_rxvt(7, b'@'), Some(KeyPress { key: KeyInput::Special(ncurses::KEY_HOME), .. })));
+        assert!(decode_rxvt(5, b'!').is_none());
+        assert!(decode_rxvt(16, b'~').is_none());
+    }
+
+    #[test]
+    fn rxvt_function_keys() {
+        const SHIFT: Modifiers = Modifiers::SHIFT;
+        const CTRL: Modifiers = Modifiers::CTRL;
+        assert!(matches!(decode_rxvt(11, b'^'), Some(KeyPress { modifiers: CTRL, key, .. }) if is_function_key(key, 1)));
+        // F13 through F20 are Shift+F3 through Shift+F10
+        assert!(matches!(decode_rxvt(25, b'~'), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 3)));
+        assert!(matches!(decode_rxvt(29, b'~'), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 6)));
+        assert!(matches!(decode_rxvt(34, b'~'), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 10)));
+        assert!(matches!(decode_rxvt(29, b'^'), Some(KeyPress { modifiers, key, .. }) if modifiers == CTRL | SHIFT && is_function_key(key, 6)));
+        assert!(matches!(decode_rxvt(29, b'$'), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 16)));
+    }
+
+    #[test]
+    fn capability_names() {
+        assert!(matches!(decode_capability_name(b"kUP"), Some(KeyPress { modifiers: Modifiers::SHIFT, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_capability_name(b"kUP5"), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode_capability_name(b"kDC3"), Some(KeyPress { modifiers: Modifiers::ALT, key: KeyInput::Special(ncurses::KEY_DC), .. })));
+        assert!(matches!(decode_capability_name(b"kpADD5"), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Keypad('+'), .. })));
+        assert!(matches!(decode_capability_name(b"kb23"), Some(KeyPress { modifiers: Modifiers::ALT, key: KeyInput::Special(ncurses::KEY_B2), .. })));
+        assert!(matches!(decode_capability_name(b"ka23"), Some(KeyPress { modifiers: Modifiers::ALT, key: KeyInput::Keypad('8'), .. })));
+    }
+
+    #[test]
+    fn not_capability_names() {
+        // Only the Shift versions have names without a suffix, and the suffix starts at 3
+        assert!(decode_capability_name(b"kpADD").is_none());
+        assert!(decode_capability_name(b"kUP2").is_none());
+        assert!(decode_capability_name(b"kUP05").is_none());
+        assert!(decode_capability_name(b"kXYZ5").is_none());
+        // Function keys have their own convention
+        assert!(decode_capability_name(b"kf13").is_none());
+    }
+
+    #[test]
+    fn extended_function_keys() {
+        const SHIFT: Modifiers = Modifiers::SHIFT;
+        const CTRL: Modifiers = Modifiers::CTRL;
+        const ALT: Modifiers = Modifiers::ALT;
+        assert!(matches!(decode_extended_function_key(13), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 1)));
+        assert!(matches!(decode_extended_function_key(24), Some(KeyPress { modifiers: SHIFT, key, .. }) if is_function_key(key, 12)));
+        assert!(matches!(decode_extended_function_key(25), Some(KeyPress { modifiers: CTRL, key, .. }) if is_function_key(key, 1)));
+        assert!(matches!(decode_extended_function_key(37), Some(KeyPress { modifiers, key, .. }) if modifiers == CTRL | SHIFT && is_function_key(key, 1)));
+        assert!(matches!(decode_extended_function_key(49), Some(KeyPress { modifiers: ALT, key, .. }) if is_function_key(key, 1)));
+        assert!(matches!(decode_extended_function_key(63), Some(KeyPress { modifiers, key, .. }) if modifiers == ALT | SHIFT && is_function_key(key, 3)));
+        assert!(decode_extended_function_key(12).is_none());
+        assert!(decode_extended_function_key(64).is_none());
+    }
+
+    #[test]
+    fn control_characters() {
+        use crate::ControlCharacterStyle::*;
+        const CTRL: Modifiers = Modifiers::CTRL;
+        assert!(matches!(decode_control_character(0x01, Caret), Some((CTRL, KeyInput::Codepoint('a')))));
+        assert!(matches!(decode_control_character(b'\r', Caret), Some((Modifiers::NONE, KeyInput::Codepoint('\n')))));
+        assert!(matches!(decode_control_character(b'\t', Caret), Some((Modifiers::NONE, KeyInput::Codepoint('\t')))));
+        assert!(matches!(decode_control_character(0x7f, Caret), Some((Modifiers::NONE, KeyInput::Special(ncurses::KEY_BACKSPACE)))));
+        assert!(matches!(decode_control_character(0x00, Caret), Some((CTRL, KeyInput::Codepoint(' ')))));
+        assert!(matches!(decode_control_character(0x00, Digits), Some((CTRL, KeyInput::Codepoint('2')))));
+        assert!(matches!(decode_control_character(0x1e, Caret), Some((CTRL, KeyInput::Codepoint('^')))));
+        assert!(matches!(decode_control_character(0x1e, UsLayout), Some((modifiers, KeyInput::Codepoint('6'))) if modifiers == CTRL | Modifiers::SHIFT));
+        assert!(matches!(decode_control_character(0x1f, UsLayout), Some((CTRL, KeyInput::Codepoint('/')))));
+        assert!(matches!(decode_control_character(0x1f, Digits), Some((CTRL, KeyInput::Codepoint('7')))));
+        assert!(decode_control_character(b'a', Caret).is_none());
+    }
+
+    #[test]
+    fn meta() {
+        use crate::ControlCharacterStyle::Caret;
+        const ALT: Modifiers = Modifiers::ALT;
+        assert!(matches!(decode_meta(b'x', Caret), KeyPress { modifiers: ALT, key: KeyInput::Codepoint('x'), ambiguous: None, .. }));
+        assert!(matches!(decode_meta(0x01, Caret), KeyPress { modifiers, key: KeyInput::Codepoint('a'), .. } if modifiers == ALT | Modifiers::CTRL));
+        assert!(matches!(decode_meta(b'\n', Caret), KeyPress { modifiers, key: KeyInput::Codepoint('j'), .. } if modifiers == ALT | Modifiers::CTRL));
+        assert!(matches!(decode_meta(b'\t', Caret), KeyPress { modifiers: ALT, key: KeyInput::Codepoint('\t'), ambiguous: Some((modifiers, KeyInput::Codepoint('i'))), .. } if modifiers == ALT | Modifiers::CTRL));
+    }
+
+    #[test]
+    fn ambiguous_alternatives() {
+        use crate::ControlCharacterStyle::*;
+        const CTRL: Modifiers = Modifiers::CTRL;
+        assert!(matches!(ambiguous_alternative(b'\t', Caret), Some((CTRL, KeyInput::Codepoint('i')))));
+        assert!(matches!(ambiguous_alternative(b'\r', Caret), Some((CTRL, KeyInput::Codepoint('m')))));
+        assert!(matches!(ambiguous_alternative(0x1b, Caret), Some((CTRL, KeyInput::Codepoint('[')))));
+        assert!(matches!(ambiguous_alternative(0x7f, Caret), Some((CTRL, KeyInput::Special(ncurses::KEY_BACKSPACE)))));
+        assert!(matches!(ambiguous_alternative(0x00, Caret), Some((CTRL, KeyInput::Codepoint('`')))));
+        assert!(matches!(ambiguous_alternative(0x00, Digits), Some((CTRL, KeyInput::Codepoint(' ')))));
+        // The digit aliases and the other styles are each other's alternative
+        assert!(matches!(ambiguous_alternative(0x1f, Caret), Some((CTRL, KeyInput::Codepoint('7')))));
+        assert!(matches!(ambiguous_alternative(0x1f, Digits), Some((CTRL, KeyInput::Codepoint('_')))));
+        assert!(ambiguous_alternative(0x01, Caret).is_none());
+        assert!(ambiguous_alternative(b'a', Caret).is_none());
+    }
+
+    #[test]
+    fn mark_ambiguous_keeps_existing() {
+        use crate::ControlCharacterStyle::Caret;
+        let tab = KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Codepoint('\t'), is_repeat: false, ambiguous: None, alternates: None };
+        assert!(matches!(mark_ambiguous(tab, b'\t', Caret), KeyPress { ambiguous: Some((Modifiers::CTRL, KeyInput::Codepoint('i'))), .. }));
+        let marked = KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Codepoint('\t'), is_repeat: false, ambiguous: Some((Modifiers::ALT, KeyInput::Codepoint('x'))), alternates: None };
+        assert!(matches!(mark_ambiguous(marked, b'\t', Caret), KeyPress { ambiguous: Some((Modifiers::ALT, KeyInput::Codepoint('x'))), .. }));
+        assert!(matches!(mark_ambiguous(FocusGained, b'\t', Caret), FocusGained));
+    }
+
+    #[test]
+    fn menu() {
+        assert!(matches!(decode_csi(&parse(b"29~")), Some(KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Menu, .. })));
+        assert!(matches!(decode_csi(&parse(b"29;5~")), Some(KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Menu, .. })));
+        // Other terminals keep the VT220's F16 there
+        assert!(matches!(decode_rxvt(29, b'^'), Some(KeyPress { key, .. }) if is_function_key(key, 6)));
+    }
+}
@@ -0,0 +1,444 @@
+use crate::{AlternateKeys, Event, KeyInput, MediaKey, Modifiers};
+use crate::KeyInput::*;
+use super::{csi, keys};
+
+// Kitty's original full keyboard mode reports every key as `ESC _ K <type> <modifiers> <key> ESC \`,
+// where the type is `p`, `r`, or `t` for press, release, or repeat, the modifiers are a single base64
+// digit, and the key is a number in Kitty's own base85 encoding.
+
+fn decode_base64_digit(byte: u8) -> Option<u32> {
+    Some(match byte {
+        b'A'..=b'Z' => byte - b'A',
+        b'a'..=b'z' => byte - b'a' + 26,
+        b'0'..=b'9' => byte - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None
+    } as u32)
+}
+
+fn decode_base85_digit(byte: u8) -> Option<u32> {
+    Some(match byte {
+        b'A'..=b'Z' => (byte - b'A') as u32,
+        b'a'..=b'z' => (byte - b'a' + 26) as u32,
+        b'0'..=b'9' => (byte - b'0' + 52) as u32,
+        _ => b".-:+=^!/*?&<>()[]{}@%$#".iter().position(|&digit| digit == byte)? as u32 + 62
+    })
+}
+
+/// Decode the body of a full keyboard mode report, i.e. everything between `ESC _` and the string
+/// terminator.
+pub fn decode(data: &[u8]) -> Option<Event> {
+    const SHIFT: Modifiers = Modifiers::SHIFT;
+
+    let (key_type, modifiers, digits) = match *data {
+        [b'K', key_type, modifiers, ref digits @ ..] if !digits.is_empty() => (key_type, modifiers, digits),
+        _ => return None
+    };
+    // The bits are Shift, Alt, Ctrl, and Super, as in `Modifiers`
+    let mut modifiers = Modifiers(decode_base64_digit(modifiers)? as u8);
+    let mut key = 0u32;
+    for &digit in digits {
+        key = key.checked_mul(85)?.checked_add(decode_base85_digit(digit)?)?;
+    }
+
+    // FIXME: This mode does not provide an indication of the correct capital version of a shifted
+    // key; decide on a strategy for dealing with that since keyboard layouts aren't always consistent.
+    // Kitty's newer keyboard protocol reports the shifted key, so this only matters for old versions.
+    // Note that without Ctrl or Alt, this protocol is not used, so the capital variants are available
+    let translated = match key {
+        0 => Codepoint(' '),
+        1 if !modifiers.contains(SHIFT) => Codepoint('\''),
+        2 if !modifiers.contains(SHIFT) => Codepoint(','),
+        3 if !modifiers.contains(SHIFT) => Codepoint('-'),
+        4 if !modifiers.contains(SHIFT) => Codepoint('.'),
+        5 if !modifiers.contains(SHIFT) => Codepoint('/'),
+        6..=15 if !modifiers.contains(SHIFT) => {
+            Codepoint(std::char::from_u32('0' as u32 + key - 6).unwrap())
+        },
+        16 if !modifiers.contains(SHIFT) => Codepoint(';'),
+        17 if !modifiers.contains(SHIFT) => Codepoint('='),
+        18..=43 => if !modifiers.contains(SHIFT) { // If shift, capitalize the letter
+            Codepoint(std::char::from_u32('a' as u32 + key - 18).unwrap())
+        } else {
+            Codepoint(std::char::from_u32('A' as u32 + key - 18).unwrap())
+        },
+        44 if !modifiers.contains(SHIFT) => Codepoint('['),
+        45 if !modifiers.contains(SHIFT) => Codepoint('\\'),
+        46 if !modifiers.contains(SHIFT) => Codepoint(']'),
+        47 if !modifiers.contains(SHIFT) => Codepoint('`'),
+        50 => Codepoint('\u{1b}'), // Escape
+        51 => Codepoint('\n'),
+        52 => Codepoint('\t'),
+        53 => Special(ncurses::KEY_BACKSPACE),
+        54 => Special(ncurses::KEY_IC),
+        55 => Special(ncurses::KEY_DC),
+        56 => Special(ncurses::KEY_RIGHT),
+        57 => Special(ncurses::KEY_LEFT),
+        58 => Special(ncurses::KEY_DOWN),
+        59 => Special(ncurses::KEY_UP),
+        60 => Special(ncurses::KEY_PPAGE),
+        61 => Special(ncurses::KEY_NPAGE),
+        62 => Special(ncurses::KEY_HOME),
+        63 => Special(ncurses::KEY_END),
+        64 => CapsLock,
+        65 => ScrollLock,
+        66 => NumLock,
+        67 => PrintScreen,
+        68 => Pause,
+        69..=93 => keys::function_key(key as i32 - 68),
+        // NumLock isn't reported, so assume that it is on. As in X11, Shift turns it off while held.
+        94..=104 => {
+            let character = std::char::from_digit(key - 94, 10).unwrap_or('.');
+            let num_lock = !modifiers.contains(SHIFT);
+            if !num_lock {
+                modifiers = modifiers.remove(SHIFT);
+            }
+            keys::keypad_key_with_num_lock(character, num_lock)
+        },
+        105 => Keypad('/'),
+        106 => Keypad('*'),
+        107 => Keypad('-'),
+        108 => Keypad('+'),
+        109 => Keypad('\n'),
+        110 => Keypad('='),
+        // End of keypad inputs
+        111 => LeftShift,
+        112 => LeftCtrl,
+        113 => LeftAlt,
+        114 => LeftSuper,
+        115 => RightShift,
+        116 => RightCtrl,
+        117 => RightAlt,
+        118 => RightSuper,
+        119 if !modifiers.contains(SHIFT) => Codepoint('+'),
+        120 if !modifiers.contains(SHIFT) => Codepoint('_'),
+        121 => Menu,
+        122 if !modifiers.contains(SHIFT) => Codepoint('!'),
+        123 if !modifiers.contains(SHIFT) => Codepoint('"'),
+        124 if !modifiers.contains(SHIFT) => Codepoint('#'),
+        125 if !modifiers.contains(SHIFT) => Codepoint('$'),
+        126 if !modifiers.contains(SHIFT) => Codepoint('&'),
+        127 if !modifiers.contains(SHIFT) => Codepoint('('),
+        128 if !modifiers.contains(SHIFT) => Codepoint(')'),
+        129 if !modifiers.contains(SHIFT) => Codepoint(':'),
+        130 if !modifiers.contains(SHIFT) => Codepoint('<'),
+        131 if !modifiers.contains(SHIFT) => Codepoint('>'),
+        132 if !modifiers.contains(SHIFT) => Codepoint('@'),
+        // FIXME: see ù
+        135 if !modifiers.contains(SHIFT) => Codepoint('à'), // else Codepoint('À')
+        136 => if !modifiers.contains(SHIFT) {
+            Codepoint('ä')
+        } else {
+            Codepoint('Ä')
+        },
+        137 => if !modifiers.contains(SHIFT) {
+            Codepoint('å')
+        } else {
+            Codepoint('Å')
+        },
+        138 => if !modifiers.contains(SHIFT) {
+            Codepoint('æ')
+        } else {
+            Codepoint('Æ')
+        },
+        // FIXME: see ù
+        139 if !modifiers.contains(SHIFT) => Codepoint('ç'), // else Codepoint('Ç')
+        // FIXME: see ù
+        140 if !modifiers.contains(SHIFT) => Codepoint('è'), // else Codepoint('È')
+        // FIXME: see ù
+        141 if !modifiers.contains(SHIFT) => Codepoint('é'), // else Codepoint('É')
+        142 => if !modifiers.contains(SHIFT) {
+            Codepoint('ì')
+        } else {
+            Codepoint('Ì')
+        },
+        143 => if !modifiers.contains(SHIFT) {
+            Codepoint('ñ')
+        } else {
+            Codepoint('Ñ')
+        },
+        144 => if !modifiers.contains(SHIFT) {
+            Codepoint('ò')
+        } else {
+            Codepoint('Ò')
+        },
+        145 => if !modifiers.contains(SHIFT) {
+            Codepoint('ö')
+        } else {
+            Codepoint('Ö')
+        },
+        146 => if !modifiers.contains(SHIFT) {
+            Codepoint('ø')
+        } else {
+            Codepoint('Ø')
+        },
+        // FIXME
+        // We could easily capitalize this on Shift, but it is unclear if we
+        // should; the AZERTY layout is probably the most likely source of a
+        // 'ù' key press, and the Shifted version of that key is %, not Ù.
+        // All of this is somewhat broken anyway with AltGr, since that
+        // isn't sent at all.
+        // Note that this is only a problem for Alt+Shift and Ctrl+Shift.
+        147 if !modifiers.contains(SHIFT) => Codepoint('ù'),
+        148 => if !modifiers.contains(SHIFT) {
+            Codepoint('ü')
+        } else {
+            Codepoint('Ü')
+        },
+        // For now, the sharp S does not typically share a key with its capital
+        149 if !modifiers.contains(SHIFT) => Codepoint('ß'),
+        150..=181 => if !modifiers.contains(SHIFT) { // Cyrillic characters
+            Codepoint(std::char::from_u32('а' as u32 + key - 150).unwrap())
+        } else {
+            Codepoint(std::char::from_u32('А' as u32 + key - 150).unwrap())
+        },
+        // Ie with grave (ѐ) is skipped
+        182 => if !modifiers.contains(SHIFT) {
+            Codepoint('ё')
+        } else {
+            Codepoint('Ё')
+        },
+        183 if !modifiers.contains(SHIFT) => Codepoint('\u{0302}'), // Circumflex
+        _ => Special(key as i32 + 600)
+    };
+
+    Some(match key_type {
+        b'p' => Event::KeyPress { modifiers, key: translated, is_repeat: false, ambiguous: None, alternates: None },
+        b't' => Event::KeyPress { modifiers, key: translated, is_repeat: true, ambiguous: None, alternates: None },
+        b'r' => Event::KeyRelease { modifiers, key: translated, alternates: None },
+        _ => return None
+    })
+}
+
+// Keys that the keyboard protocol sends as `CSI n u` with a number from the private use area, as
+// they have no character or legacy encoding
+fn functional_key(number: u32) -> Option<KeyInput> {
+    Some(match number {
+        57358 => KeyInput::CapsLock,
+        57359 => KeyInput::ScrollLock,
+        57360 => KeyInput::NumLock,
+        57361 => KeyInput::PrintScreen,
+        57362 => KeyInput::Pause,
+        57363 => KeyInput::Menu,
+        57376..=57398 => keys::function_key(number as i32 - 57376 + 13),
+        57399..=57408 => KeyInput::Keypad((b'0' + (number - 57399) as u8) as char),
+        57409 => KeyInput::Keypad('.'),
+        57410 => KeyInput::Keypad('/'),
+        57411 => KeyInput::Keypad('*'),
+        57412 => KeyInput::Keypad('-'),
+        57413 => KeyInput::Keypad('+'),
+        57414 => KeyInput::Keypad('\n'),
+        57415 => KeyInput::Keypad('='),
+        57416 => KeyInput::Keypad(','),
+        57417 => KeyInput::Special(ncurses::KEY_LEFT),
+        57418 => KeyInput::Special(ncurses::KEY_RIGHT),
+        57419 => KeyInput::Special(ncurses::KEY_UP),
+        57420 => KeyInput::Special(ncurses::KEY_DOWN),
+        57421 => KeyInput::Special(ncurses::KEY_PPAGE),
+        57422 => KeyInput::Special(ncurses::KEY_NPAGE),
+        57423 => KeyInput::Special(ncurses::KEY_HOME),
+        57424 => KeyInput::Special(ncurses::KEY_END),
+        57425 => KeyInput::Special(ncurses::KEY_IC),
+        57426 => KeyInput::Special(ncurses::KEY_DC),
+        57427 => KeyInput::Special(ncurses::KEY_B2),
+        57428 => KeyInput::Media(MediaKey::Play),
+        57429 => KeyInput::Media(MediaKey::Pause),
+        57430 => KeyInput::Media(MediaKey::PlayPause),
+        57431 => KeyInput::Media(MediaKey::Reverse),
+        57432 => KeyInput::Media(MediaKey::Stop),
+        57433 => KeyInput::Media(MediaKey::FastForward),
+        57434 => KeyInput::Media(MediaKey::Rewind),
+        57435 => KeyInput::Media(MediaKey::NextTrack),
+        57436 => KeyInput::Media(MediaKey::PreviousTrack),
+        57437 => KeyInput::Media(MediaKey::Record),
+        57438 => KeyInput::Media(MediaKey::VolumeDown),
+        57439 => KeyInput::Media(MediaKey::VolumeUp),
+        57440 => KeyInput::Media(MediaKey::Mute),
+        // Modifier keys are only reported if every key is sent as an escape code, which we don't
+        // ask for but an application might
+        57441 => KeyInput::LeftShift,
+        57442 => KeyInput::LeftCtrl,
+        57443 => KeyInput::LeftAlt,
+        57444 => KeyInput::LeftSuper,
+        57447 => KeyInput::RightShift,
+        57448 => KeyInput::RightCtrl,
+        57449 => KeyInput::RightAlt,
+        57450 => KeyInput::RightSuper,
+        _ => return None
+    })
+}
+
+// A key in the keyboard protocol, given as a Unicode codepoint or one of the functional key numbers
+fn key_from_number(number: u32) -> Option<KeyInput> {
+    Some(match number {
+        9 => Codepoint('\t'),
+        13 => Codepoint('\n'),
+        27 => Codepoint('\u{1b}'),
+        127 => Special(ncurses::KEY_BACKSPACE),
+        57344..=63743 => functional_key(number)?,
+        _ => Codepoint(std::char::from_u32(number)?)
+    })
+}
+
+/// Build the event for a key given the event type that kitty's keyboard protocol reports, which is 1
+/// for a press, 2 for a repeat, and 3 for a release.
+pub fn key_event(modifiers: Modifiers, key: KeyInput, event_type: u32, alternates: Option<AlternateKeys>) -> Option<Event> {
+    Some(match event_type {
+        1 => Event::KeyPress { modifiers, key, is_repeat: false, ambiguous: None, alternates },
+        2 => Event::KeyPress { modifiers, key, is_repeat: true, ambiguous: None, alternates },
+        3 => Event::KeyRelease { modifiers, key, alternates },
+        _ => return None
+    })
+}
+
+/// Decode a key in kitty's current keyboard protocol, `CSI key : shifted : base ; modifiers : type u`,
+/// where everything after the key is optional. The shifted key and the key in the standard US
+/// layout are only there if we asked for alternate keys and they are different from the key itself.
+/// With Shift held, the shifted key is reported as the key in place of Shift, so that e.g.
+/// Ctrl+Shift+/ comes through as Ctrl+?.
+pub fn decode_csi(sequence: &csi::ControlSequence) -> Option<Event> {
+    let (number, shifted, base_layout) = match (&sequence.parameters[..], &sequence.subparameters[..]) {
+        (&[number, ..], [alternates, ..]) => match alternates[..] {
+            [] => (number, 0, 0),
+            [shifted] => (number, shifted, 0),
+            [shifted, base_layout] => (number, shifted, base_layout),
+            _ => return None
+        },
+        _ => return None
+    };
+    // The text that the key produced may follow, which we don't need
+    let (modifiers, event_type) = match (sequence.parameters.get(1), sequence.subparameters.get(1)) {
+        (None, _) => (Modifiers::NONE, 1),
+        (Some(&modifiers), Some(event_type)) => match event_type[..] {
+            [] => (Modifiers::from_xterm_param(modifiers.max(1))?, 1),
+            [event_type] => (Modifiers::from_xterm_param(modifiers.max(1))?, event_type),
+            _ => return None
+        },
+        _ => return None
+    };
+
+    let unshifted = key_from_number(number)?;
+    // Shift is used up by the shifted key, unless that is a key we can't name
+    let (modifiers, key) = match key_from_number(shifted) {
+        Some(key) if shifted != 0 && modifiers.contains(Modifiers::SHIFT) => (modifiers.remove(Modifiers::SHIFT), key),
+        _ => (modifiers, unshifted)
+    };
+    let base_layout = match base_layout {
+        0 => None,
+        base_layout => Some(key_from_number(base_layout)?)
+    };
+    key_event(modifiers, key, event_type, Some(AlternateKeys { unshifted, base_layout }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_mode() {
+        assert!(matches!(decode(b"KpES"), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Codepoint('a'), is_repeat: false, .. })));
+        assert!(matches!(decode(b"KtCS"), Some(Event::KeyPress { modifiers: Modifiers::ALT, key: Codepoint('a'), is_repeat: true, .. })));
+        assert!(matches!(decode(b"KrBS"), Some(Event::KeyRelease { modifiers: Modifiers::SHIFT, key: Codepoint('A'), .. })));
+        assert!(matches!(decode(b"KpEG"), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Codepoint('0'), .. })));
+        assert!(matches!(decode(b"KpE7"), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(decode(b"KpABb"), Some(Event::KeyPress { modifiers: Modifiers::NONE, key: LeftCtrl, .. })));
+    }
+
+    #[test]
+    fn full_mode_shifted_symbols() {
+        // Without a way to know the shifted symbol, these fall through to a number
+        assert!(matches!(decode(b"KpFF"), Some(Event::KeyPress { key: Special(605), .. })));
+        assert!(matches!(decode(b"KpEF"), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Codepoint('/'), .. })));
+    }
+
+    #[test]
+    fn full_mode_malformed() {
+        assert!(decode(b"").is_none());
+        assert!(decode(b"KpA").is_none());
+        assert!(decode(b"KxAS").is_none());
+        assert!(decode(b"Kp~S").is_none());
+        assert!(decode(b"KpA~").is_none());
+        assert!(decode(b"JpAS").is_none());
+        // Too large for a key number
+        assert!(decode(b"KpAzzzzzzzz").is_none());
+    }
+
+    #[test]
+    fn full_mode_lock_and_menu_keys() {
+        assert!(matches!(decode(b"KpA:"), Some(Event::KeyPress { key: CapsLock, .. })));
+        assert!(matches!(decode(b"KpA!"), Some(Event::KeyPress { key: Pause, .. })));
+        assert!(matches!(decode(b"KpABk"), Some(Event::KeyPress { key: Menu, .. })));
+        assert!(matches!(decode(b"KpA/"), Some(Event::KeyPress { key: Special(code), .. }) if code == ncurses::KEY_F0 + 1));
+    }
+
+    fn parse(bytes: &[u8]) -> csi::ControlSequence {
+        let mut bytes = bytes.iter().cloned();
+        csi::parse(|| bytes.next()).unwrap()
+    }
+
+    #[test]
+    fn keyboard_protocol() {
+        assert!(matches!(decode_csi(&parse(b"97u")), Some(Event::KeyPress { modifiers: Modifiers::NONE, key: Codepoint('a'), is_repeat: false, .. })));
+        assert!(matches!(decode_csi(&parse(b"97;5u")), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Codepoint('a'), .. })));
+        assert!(matches!(decode_csi(&parse(b"13;3u")), Some(Event::KeyPress { modifiers: Modifiers::ALT, key: Codepoint('\n'), .. })));
+        assert!(matches!(decode_csi(&parse(b"127u")), Some(Event::KeyPress { key: Special(ncurses::KEY_BACKSPACE), .. })));
+        assert!(matches!(decode_csi(&parse(b"57363u")), Some(Event::KeyPress { key: Menu, .. })));
+        assert!(matches!(decode_csi(&parse(b"57399;5u")), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Keypad('0'), .. })));
+        assert!(matches!(decode_csi(&parse(b"57441;2u")), Some(Event::KeyPress { key: LeftShift, .. })));
+    }
+
+    #[test]
+    fn keyboard_protocol_event_types() {
+        assert!(matches!(decode_csi(&parse(b"97;5:1u")), Some(Event::KeyPress { modifiers: Modifiers::CTRL, is_repeat: false, .. })));
+        assert!(matches!(decode_csi(&parse(b"97;5:2u")), Some(Event::KeyPress { modifiers: Modifiers::CTRL, is_repeat: true, .. })));
+        assert!(matches!(decode_csi(&parse(b"97;5:3u")), Some(Event::KeyRelease { modifiers: Modifiers::CTRL, key: Codepoint('a'), .. })));
+        assert!(matches!(decode_csi(&parse(b"97;:3u")), Some(Event::KeyRelease { modifiers: Modifiers::NONE, .. })));
+        assert!(decode_csi(&parse(b"97;5:4u")).is_none());
+        // The same event types on the keys that keep their legacy encoding
+        assert!(matches!(keys::decode_csi(&parse(b"1;5:3A")), Some(Event::KeyRelease { modifiers: Modifiers::CTRL, key: Special(ncurses::KEY_UP), .. })));
+        assert!(matches!(keys::decode_csi(&parse(b"5;1:2~")), Some(Event::KeyPress { modifiers: Modifiers::NONE, key: Special(ncurses::KEY_PPAGE), is_repeat: true, .. })));
+    }
+
+    #[test]
+    fn keyboard_protocol_shifted_keys() {
+        // Shift is used up by the shifted key
+        assert!(matches!(decode_csi(&parse(b"97:65;6u")), Some(Event::KeyPress {
+            modifiers: Modifiers::CTRL,
+            key: Codepoint('A'),
+            alternates: Some(AlternateKeys { unshifted: Codepoint('a'), base_layout: None }),
+            ..
+        })));
+        assert!(matches!(decode_csi(&parse(b"47:63;6u")), Some(Event::KeyPress { modifiers: Modifiers::CTRL, key: Codepoint('?'), .. })));
+        // A shifted key without Shift, e.g. from Caps Lock, is only an alternative
+        assert!(matches!(decode_csi(&parse(b"97:65;65u")), Some(Event::KeyPress { key: Codepoint('a'), .. })));
+        // A shifted key that can't be named leaves the unshifted key and Shift
+        assert!(matches!(decode_csi(&parse(b"47:1114112;6u")), Some(Event::KeyPress { modifiers, key: Codepoint('/'), .. }) if modifiers == Modifiers::CTRL | Modifiers::SHIFT));
+    }
+
+    #[test]
+    fn keyboard_protocol_base_layout_keys() {
+        assert!(matches!(decode_csi(&parse(b"1089::99;5u")), Some(Event::KeyPress {
+            modifiers: Modifiers::CTRL,
+            key: Codepoint('\u{441}'),
+            alternates: Some(AlternateKeys { unshifted: Codepoint('\u{441}'), base_layout: Some(Codepoint('c')) }),
+            ..
+        })));
+        assert!(matches!(decode_csi(&parse(b"1089:1057:99;6u")), Some(Event::KeyPress {
+            modifiers: Modifiers::CTRL,
+            key: Codepoint('\u{421}'),
+            alternates: Some(AlternateKeys { unshifted: Codepoint('\u{441}'), base_layout: Some(Codepoint('c')) }),
+            ..
+        })));
+    }
+
+    #[test]
+    fn keyboard_protocol_malformed() {
+        assert!(decode_csi(&parse(b"u")).is_none());
+        assert!(decode_csi(&parse(b"97;300u")).is_none());
+        assert!(decode_csi(&parse(b"97:65:99:1u")).is_none());
+        assert!(decode_csi(&parse(b"97;5:3:1u")).is_none());
+        assert!(decode_csi(&parse(b"55296u")).is_none());
+        assert!(decode_csi(&parse(b"57999u")).is_none());
+    }
+}
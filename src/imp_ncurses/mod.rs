@@ -1,11 +1,18 @@
 use std::io::Write;
-use std::ffi::CStr;
-use const_cstr::ConstCStr;
+use std::ffi::CString;
+use std::collections::VecDeque;
 
 use crate::Event::*;
-use crate::{Event, Modifiers, KeyInput};
+use crate::{Event, Modifiers, KeyInput, TerminalColor, BindError, ClipboardError, ControlCharacterStyle, HighBitInput};
 
 mod ext;
+mod trie;
+mod csi;
+mod osc;
+mod dcs;
+mod keys;
+mod mouse;
+mod kitty;
 
 fn write_now(data: &[u8]) -> Result<(), std::io::Error> {
     let stdout = std::io::stdout();
@@ -15,6 +22,25 @@ fn write_now(data: &[u8]) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+fn terminfo_string(name: &str) -> Option<&'static [u8]> {
+    let name = CString::new(name).ok()?;
+    ext::get_terminfo_string(&name).map(|definition| definition.to_bytes())
+}
+
+// How many extended terminfo capabilities to look through for keys. Real entries have a few hundred
+// at most.
+const MAX_EXTENDED_CAPABILITIES: i32 = 1024;
+
+// The names of the extended key capabilities in the terminfo entry, e.g. kUP5 or kpADD. ncurses
+// gives these key codes above KEY_MAX, in the order of all extended capabilities, so the codes of
+// capabilities that aren't keys leave gaps. It also leaves out keys with the same definition as
+// another key. This only works once keypad mode has been enabled, which is when ncurses loads them.
+fn extended_key_names() -> Vec<String> {
+    (ncurses::KEY_MAX..ncurses::KEY_MAX + MAX_EXTENDED_CAPABILITIES).filter_map(ncurses::keyname)
+        .filter(|name| name.starts_with('k'))
+        .collect()
+}
+
 struct BracketedPaste {
     _priv: ()
 }
@@ -32,6 +58,76 @@ impl BracketedPaste {
     }
 }
 
+struct FocusReporting {
+    _priv: ()
+}
+
+impl Drop for FocusReporting {
+    fn drop(&mut self) {
+        let _ = write_now(b"\x1b[?1004l");
+    }
+}
+
+impl FocusReporting {
+    fn start() -> Option<FocusReporting> {
+        write_now(b"\x1b[?1004h").ok()?;
+        Some(FocusReporting { _priv: () })
+    }
+}
+
+// Button presses and releases, in the SGR encoding if the terminal supports it and the X10 encoding
+// otherwise
+struct MouseReporting {
+    _priv: ()
+}
+
+impl Drop for MouseReporting {
+    fn drop(&mut self) {
+        let _ = write_now(b"\x1b[?1006l\x1b[?1000l");
+    }
+}
+
+impl MouseReporting {
+    fn start() -> Option<MouseReporting> {
+        write_now(b"\x1b[?1000h\x1b[?1006h").ok()?;
+        Some(MouseReporting { _priv: () })
+    }
+}
+
+struct InBandResize {
+    _priv: ()
+}
+
+impl Drop for InBandResize {
+    fn drop(&mut self) {
+        let _ = write_now(b"\x1b[?2048l");
+    }
+}
+
+impl InBandResize {
+    fn start() -> Option<InBandResize> {
+        write_now(b"\x1b[?2048h").ok()?;
+        Some(InBandResize { _priv: () })
+    }
+}
+
+struct ColorSchemeNotifications {
+    _priv: ()
+}
+
+impl Drop for ColorSchemeNotifications {
+    fn drop(&mut self) {
+        let _ = write_now(b"\x1b[?2031l");
+    }
+}
+
+impl ColorSchemeNotifications {
+    fn start() -> Result<ColorSchemeNotifications, std::io::Error> {
+        write_now(b"\x1b[?2031h")?;
+        Ok(ColorSchemeNotifications { _priv: () })
+    }
+}
+
 struct XTermModifyOtherKeys {
     _priv: ()
 }
@@ -49,10 +145,82 @@ impl XTermModifyOtherKeys {
     }
 }
 
-enum XTermModifyKeyState {
-    Off,
-    ParsingMode(u32),
-    ParsingChar(u32, u32)
+// DECCKM and DECKPAM, which decide whether the cursor keys and the numeric keypad send `SS3`
+// sequences. Both forms of every key are recognized either way, but application keypad mode is what
+// lets us tell the keypad apart from the main keyboard. Other programs can change these modes behind
+// our back, so we remember what was asked for in order to send it again.
+struct KeyModes {
+    application_cursor_keys: bool,
+    application_keypad: bool
+}
+
+impl Drop for KeyModes {
+    fn drop(&mut self) {
+        // Leave the terminal in the normal modes, like terminfo's rmkx
+        let _ = write_now(b"\x1b[?1l\x1b>");
+    }
+}
+
+impl KeyModes {
+    fn start() -> KeyModes {
+        let modes = KeyModes { application_cursor_keys: false, application_keypad: true };
+        let _ = modes.send();
+        modes
+    }
+
+    fn send(&self) -> Result<(), std::io::Error> {
+        let cursor_keys: &[u8] = if self.application_cursor_keys { b"\x1b[?1h" } else { b"\x1b[?1l" };
+        let keypad: &[u8] = if self.application_keypad { b"\x1b=" } else { b"\x1b>" };
+        write_now(&[cursor_keys, keypad].concat())
+    }
+}
+
+// 8-bit meta mode, in which terminals send Alt by setting the high bit of a byte. Unlike the other
+// modes, terminfo says how to switch this one, since it isn't standardized.
+struct MetaMode {
+    _priv: ()
+}
+
+impl Drop for MetaMode {
+    fn drop(&mut self) {
+        if let Some(rmm) = terminfo_string("rmm") {
+            let _ = write_now(rmm);
+        }
+    }
+}
+
+impl MetaMode {
+    fn start() -> Result<Option<MetaMode>, std::io::Error> {
+        match terminfo_string("smm") {
+            Some(smm) => {
+                write_now(smm)?;
+                Ok(Some(MetaMode { _priv: () }))
+            },
+            None => Ok(None)
+        }
+    }
+}
+
+// Kitty's current keyboard protocol, which replaced the full keyboard mode. We ask for keys that
+// would otherwise be ambiguous (1), event types (2), and alternate keys (4). Reporting every key as
+// an escape code (8) would also tell us about modifier keys pressed alone, but then text typed
+// with AltGr, Caps Lock, or dead keys only comes through as the key that was pressed. The previous
+// flags are restored on exit.
+struct KittyKeyboardProtocol {
+    _priv: ()
+}
+
+impl Drop for KittyKeyboardProtocol {
+    fn drop(&mut self) {
+        let _ = write_now(b"\x1b[<u");
+    }
+}
+
+impl KittyKeyboardProtocol {
+    fn start() -> Option<KittyKeyboardProtocol> {
+        write_now(b"\x1b[>7u").ok()?;
+        Some(KittyKeyboardProtocol { _priv: () })
+    }
 }
 
 struct KittyFullMode {
@@ -72,247 +240,595 @@ impl KittyFullMode {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum KeyType {
-    Press,
-    Release,
-    Repeat
-}
+// How long to wait for the rest of a terminal report once it has started arriving, in milliseconds.
+// Long reports such as clipboard contents may be split up in transit.
+const REPORT_TIMEOUT: i32 = 100;
+
+// The longest operating system command that we keep, other than clipboard contents. Color reports
+// are much shorter than this.
+const MAX_REPORT_LENGTH: usize = 256;
+
+// The longest selection parameter that comes before clipboard contents, e.g. `c;`, which is every
+// one of xterm's 12 selection names and the `;`
+const MAX_SELECTION_LENGTH: usize = 13;
 
-enum KittyFullModeState {
-    Off,
-    ParsingType,
-    ParsingModifiers(KeyType),
-    ParsingKey(KeyType, u8, u32)
+// The result of reading ahead for a terminal report
+enum Report {
+    Event(Event),
+    // A report that we recognized but that should not be passed on, e.g. because it was unrequested
+    Ignored
 }
 
 pub struct InputStream {
     _bracketed_paste: Option<BracketedPaste>,
+    _focus_reporting: Option<FocusReporting>,
+    _mouse_reporting: Option<MouseReporting>,
+    _in_band_resize: Option<InBandResize>,
+    color_scheme_notifications: Option<ColorSchemeNotifications>,
+    // The maximum size of clipboard contents that we asked for, if a clipboard read is in progress
+    clipboard_limit: Option<usize>,
+    // Capabilities that the application asked the terminal for, which get reported as events
+    requested_capabilities: Vec<Vec<u8>>,
+    // How many batches of XTGETTCAP queries may still have replies on the way. Each batch is
+    // followed by a primary device attributes query, which every terminal answers, and since replies
+    // arrive in order, that answer means the batch is done. Only while this is nonzero do we treat
+    // `\x1bP` as the start of a reply instead of Alt+P.
+    pending_capability_queries: usize,
     _xterm_modify_keys: Option<XTermModifyOtherKeys>,
+    key_modes: KeyModes,
     _kitty_full_mode: Option<KittyFullMode>,
-
-    extra_bound_keys: Vec<(i32, Event)>,
-    
+    _kitty_keyboard_protocol: Option<KittyKeyboardProtocol>,
+
+    // The key sequences that we know, from terminfo and from the terminal itself
+    sequences: trie::SequenceTrie<Event>,
+    // How long to wait for the rest of an escape sequence, in milliseconds
+    escape_delay: i32,
+    control_character_style: ControlCharacterStyle,
+    high_bit_input: HighBitInput,
+    meta_mode: Option<MetaMode>,
+    pending_cursor_reports: u32,
+    // Once the terminal has sent an in-band resize report, those replace SIGWINCH-based KEY_RESIZE
+    received_in_band_resize: bool,
+    // X10-style mouse reports don't say which button was released, so remember the last press
+    pressed_mouse_button: Option<u32>,
+
+    // Inputs that were read ahead while looking for a control sequence but turned out not to be one
+    lookahead: VecDeque<i32>,
+    // An event that was read while checking for AltSendsEscape, but couldn't be combined with Alt
+    queued_event: Option<Event>,
     in_progress_codepoint: u32,
     utf8_bytes_left: usize,
-    xterm_modify_key_state: XTermModifyKeyState,
-    kitty_full_mode_state: KittyFullModeState
-
-}
-
-unsafe fn define_if_necessary(def: &std::ffi::CStr, code: std::os::raw::c_int) -> Result<(), ()> {
-    if ext::key_code_for(def) == Err(ext::KeyError::NotDefined) {
-        ext::define_key_code(def, code)
-    } else {
-        Ok(())
-    }
 }
 
-// TODO: instead parse any keys with numbers after them according to this pattern
-const KNOWN_EXTRA_TERM_CAPABILITIES: &[(ConstCStr, Event)] = &[
-    (const_cstr!("kDC3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-    (const_cstr!("kDC4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-    (const_cstr!("kDC5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-    (const_cstr!("kDC6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-    (const_cstr!("kDC7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-    (const_cstr!("kDC8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_DC), is_repeat: false }),
-
-    (const_cstr!("kLFT3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-    (const_cstr!("kLFT4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-    (const_cstr!("kLFT5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-    (const_cstr!("kLFT6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-    (const_cstr!("kLFT7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-    (const_cstr!("kLFT8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_LEFT), is_repeat: false }),
-
-    (const_cstr!("kRIT3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-    (const_cstr!("kRIT4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-    (const_cstr!("kRIT5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-    (const_cstr!("kRIT6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-    (const_cstr!("kRIT7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-    (const_cstr!("kRIT8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_RIGHT), is_repeat: false }),
-
-    (const_cstr!("kUP3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-    (const_cstr!("kUP4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-    (const_cstr!("kUP5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-    (const_cstr!("kUP6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-    (const_cstr!("kUP7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-    (const_cstr!("kUP8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_UP), is_repeat: false }),
-
-    (const_cstr!("kDN3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-    (const_cstr!("kDN4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-    (const_cstr!("kDN5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-    (const_cstr!("kDN6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-    (const_cstr!("kDN7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-    (const_cstr!("kDN8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_DOWN), is_repeat: false }),
-
-    (const_cstr!("kHOM3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-    (const_cstr!("kHOM4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-    (const_cstr!("kHOM5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-    (const_cstr!("kHOM6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-    (const_cstr!("kHOM7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-    (const_cstr!("kHOM8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_HOME), is_repeat: false }),
-
-    (const_cstr!("kEND3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-    (const_cstr!("kEND4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-    (const_cstr!("kEND5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-    (const_cstr!("kEND6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-    (const_cstr!("kEND7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-    (const_cstr!("kEND8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_END), is_repeat: false }),
-
-    (const_cstr!("kPRV3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-    (const_cstr!("kPRV4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-    (const_cstr!("kPRV5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-    (const_cstr!("kPRV6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-    (const_cstr!("kPRV7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-    (const_cstr!("kPRV8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_PPAGE), is_repeat: false }),
-
-    (const_cstr!("kNXT3"), KeyPress { modifiers: Modifiers(3 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-    (const_cstr!("kNXT4"), KeyPress { modifiers: Modifiers(4 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-    (const_cstr!("kNXT5"), KeyPress { modifiers: Modifiers(5 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-    (const_cstr!("kNXT6"), KeyPress { modifiers: Modifiers(6 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-    (const_cstr!("kNXT7"), KeyPress { modifiers: Modifiers(7 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-    (const_cstr!("kNXT8"), KeyPress { modifiers: Modifiers(8 - 1), key: KeyInput::Special(ncurses::KEY_NPAGE), is_repeat: false }),
-];
-
 impl InputStream {
     pub unsafe fn init(window: ncurses::WINDOW) -> Self {
+        // We match escape sequences ourselves, so have ncurses pass through every byte untouched,
+        // including carriage returns. Keypad mode is still turned on for a moment, since that is
+        // what makes ncurses load the extended key capabilities that we look up.
         // TODO: error handling?
         ncurses::ll::keypad(window, true as ncurses::ll::c_bool);
+        ncurses::ll::keypad(window, false as ncurses::ll::c_bool);
         ncurses::ll::raw();
         ncurses::ll::noecho();
+        ncurses::ll::nonl();
 
         // TODO: Make this configurable to allow for mouse movements and for clicks
-        ncurses::ll::mousemask(ncurses::ALL_MOUSE_EVENTS as ncurses::mmask_t, core::ptr::null_mut());
-        ncurses::ll::mouseinterval(0); // We care about up/down, not clicks
+        let mouse_reporting_guard = MouseReporting::start();
 
-        // Start bracketed paste mode, but only if we can successfully handle the brackets
         // TODO: Should we query support first?
-        let bracketed_paste_guard = if ext::define_key_code(const_cstr!("\x1b[200~").as_cstr(), 2000).is_ok() &&
-                                       ext::define_key_code(const_cstr!("\x1b[201~").as_cstr(), 2001).is_ok() {
-            BracketedPaste::start()
-        } else {
-            None
-        };
+        let bracketed_paste_guard = BracketedPaste::start();
+        let focus_reporting_guard = FocusReporting::start();
 
-        let xterm_modify_other_keys_guard = if ext::define_key_code(const_cstr!("\x1b[27;").as_cstr(), 2100).is_ok() {
-            XTermModifyOtherKeys::start()
-        } else {
-            None
-        };
+        // Unsupporting terminals will just ignore this, and supporting terminals will send us a report
+        // of the current size immediately.
+        let in_band_resize_guard = InBandResize::start();
+
+        let xterm_modify_other_keys_guard = XTermModifyOtherKeys::start();
+        let key_modes = KeyModes::start();
 
         // TODO: Should we query support first?
-        let kitty_full_mode_guard = if ext::define_key_code(const_cstr!("\x1b_K").as_cstr(), 2200).is_ok() &&
-                                       ext::define_key_code(const_cstr!("\x1b\\").as_cstr(), 2201).is_ok() {
-            KittyFullMode::start()
-        } else {
-            None
-        };
+        let kitty_full_mode_guard = KittyFullMode::start();
+        let kitty_keyboard_protocol_guard = KittyKeyboardProtocol::start();
 
         // We use Esc heavily and modern computers are quite fast, so unless the user has overridden it directly,
-        // set ESCDELAY to a small 25ms. The normal default of 1 second is too high.
+        // wait only a small 25ms for the rest of an escape sequence. The normal ncurses default of 1 second is too high.
         // TODO: If one of the other protocols causes the Esc key to be sent unambiguously, increase this value significantly
-        if std::env::var_os("ESCDELAY").is_none() {
-            ncurses::ll::set_escdelay(25);
+        let escape_delay = std::env::var("ESCDELAY").ok().and_then(|delay| delay.parse().ok()).unwrap_or(25);
+
+        ncurses::ll::ungetch(ncurses::KEY_RESIZE);
+
+        let mut stream = InputStream {
+            _bracketed_paste: bracketed_paste_guard,
+            _focus_reporting: focus_reporting_guard,
+            _mouse_reporting: mouse_reporting_guard,
+            _in_band_resize: in_band_resize_guard,
+            color_scheme_notifications: None,
+            clipboard_limit: None,
+            requested_capabilities: Vec::new(),
+            pending_capability_queries: 0,
+            _xterm_modify_keys: xterm_modify_other_keys_guard,
+            key_modes,
+            _kitty_full_mode: kitty_full_mode_guard,
+            _kitty_keyboard_protocol: kitty_keyboard_protocol_guard,
+
+            sequences: trie::SequenceTrie::new(),
+            escape_delay,
+            control_character_style: ControlCharacterStyle::Caret,
+            high_bit_input: HighBitInput::Utf8,
+            meta_mode: None,
+            pending_cursor_reports: 0,
+            received_in_band_resize: false,
+            pressed_mouse_button: None,
+
+            lookahead: VecDeque::new(),
+            queued_event: None,
+            in_progress_codepoint: 0,
+            utf8_bytes_left: 0,
+        };
+        stream.bind_terminfo_keys();
+        stream
+    }
+
+    pub fn set_escape_delay(&mut self, escape_delay: i32) {
+        self.escape_delay = escape_delay;
+    }
+
+    pub fn set_control_character_style(&mut self, style: ControlCharacterStyle) {
+        self.control_character_style = style;
+    }
+
+    pub fn set_high_bit_input(&mut self, high_bit_input: HighBitInput) -> Result<(), std::io::Error> {
+        self.high_bit_input = high_bit_input;
+        // Don't carry a partial character over into a different interpretation
+        self.utf8_bytes_left = 0;
+        match high_bit_input {
+            HighBitInput::Utf8 => self.meta_mode = None,
+            HighBitInput::Meta if self.meta_mode.is_none() => self.meta_mode = MetaMode::start()?,
+            HighBitInput::Meta => { }
         }
+        Ok(())
+    }
 
-        let mut extra_bound_keys = Vec::new();
-        for &(name, inp) in KNOWN_EXTRA_TERM_CAPABILITIES {
-            if let Some(description) = ext::get_terminfo_string(name.as_cstr()) {
-                if let Ok(code) = ext::key_code_for(description) {
-                    extra_bound_keys.push((code, inp));
-                }
-            }
+    pub fn set_application_cursor_keys(&mut self, enabled: bool) -> Result<(), std::io::Error> {
+        self.key_modes.application_cursor_keys = enabled;
+        self.key_modes.send()
+    }
+
+    pub fn set_application_keypad(&mut self, enabled: bool) -> Result<(), std::io::Error> {
+        self.key_modes.application_keypad = enabled;
+        self.key_modes.send()
+    }
+
+    pub fn restore_key_modes(&mut self) -> Result<(), std::io::Error> {
+        self.key_modes.send()
+    }
+
+    pub fn request_cursor_position(&mut self) -> Result<(), std::io::Error> {
+        write_now(b"\x1b[6n")?;
+        self.pending_cursor_reports += 1;
+        Ok(())
+    }
+
+    pub fn enable_color_scheme_notifications(&mut self) -> Result<(), std::io::Error> {
+        if self.color_scheme_notifications.is_none() {
+            self.color_scheme_notifications = Some(ColorSchemeNotifications::start()?);
+        }
+        Ok(())
+    }
+
+    pub fn request_color_scheme(&mut self) -> Result<(), std::io::Error> {
+        write_now(b"\x1b[?996n")
+    }
+
+    pub fn request_color(&mut self, color: TerminalColor) -> Result<(), std::io::Error> {
+        match color {
+            TerminalColor::Foreground => write_now(b"\x1b]10;?\x07"),
+            TerminalColor::Background => write_now(b"\x1b]11;?\x07"),
+            TerminalColor::Palette(index) => write_now(format!("\x1b]4;{};?\x07", index).as_bytes())
+        }
+    }
+
+    pub fn request_clipboard(&mut self, max_length: usize) -> Result<(), std::io::Error> {
+        write_now(b"\x1b]52;c;?\x07")?;
+        self.clipboard_limit = Some(max_length);
+        Ok(())
+    }
+
+    pub fn request_terminal_capability(&mut self, name: &str) -> Result<(), std::io::Error> {
+        write_now(format!("\x1bP+q{}\x1b\\\x1b[c", dcs::encode_hex(name.as_bytes())).as_bytes())?;
+        self.requested_capabilities.push(name.as_bytes().to_vec());
+        self.pending_capability_queries += 1;
+        Ok(())
+    }
+
+    pub fn request_key_capabilities(&mut self) -> Result<(), std::io::Error> {
+        // Some terminals stop answering at the first unknown name in a list, so ask one at a time
+        for name in keys::modified_key_capability_names() {
+            write_now(format!("\x1bP+q{}\x1b\\", dcs::encode_hex(name.as_bytes())).as_bytes())?;
         }
+        write_now(b"\x1b[c")?;
+        self.pending_capability_queries += 1;
+        Ok(())
+    }
 
-        // Hackily detect if our terminal is using rxvt-style codes and add the rest if necessary. Note that this
-        // should never override an existing binding, so it shouldn't cause problems even if it happens to be enabled
-        // on a terminal that uses different bindings.
-        if ext::key_code_for(const_cstr!("\x1b[A").as_cstr()) == Ok(ncurses::KEY_UP) &&
-           ext::key_code_for(const_cstr!("\x1b[B").as_cstr()) == Ok(ncurses::KEY_DOWN) &&
-           ext::key_code_for(const_cstr!("\x1b[C").as_cstr()) == Ok(ncurses::KEY_RIGHT) &&
-           ext::key_code_for(const_cstr!("\x1b[D").as_cstr()) == Ok(ncurses::KEY_LEFT) &&
-           ext::key_code_for(const_cstr!("\x1b[c").as_cstr()) == Ok(ncurses::KEY_SRIGHT) &&
-           ext::key_code_for(const_cstr!("\x1b[d").as_cstr()) == Ok(ncurses::KEY_SLEFT) {
-
-            let _ = define_if_necessary(const_cstr!("\x1bOa").as_cstr(), 2340);
-            let _ = define_if_necessary(const_cstr!("\x1bOb").as_cstr(), 2341);
-            let _ = define_if_necessary(const_cstr!("\x1bOc").as_cstr(), 2342);
-            let _ = define_if_necessary(const_cstr!("\x1bOd").as_cstr(), 2343);
-            // And AltSendsEscape versions as well (TODO: fold into a general AltSendsEscape mechanism)
-            let _ = define_if_necessary(const_cstr!("\x1b\x1bOa").as_cstr(), 2360);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1bOb").as_cstr(), 2361);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1bOc").as_cstr(), 2362);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1bOd").as_cstr(), 2363);
-
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[A").as_cstr(), 2320);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[B").as_cstr(), 2321);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[C").as_cstr(), 2322);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[D").as_cstr(), 2323);
-
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[a").as_cstr(), 2330);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[b").as_cstr(), 2331);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[c").as_cstr(), 2332);
-            let _ = define_if_necessary(const_cstr!("\x1b\x1b[d").as_cstr(), 2333);
-
-            if ext::key_code_for(const_cstr!("\x1b[3~").as_cstr()) == Ok(ncurses::KEY_DC) {
-                let _ = define_if_necessary(const_cstr!("\x1b[3^").as_cstr(), 2348);
-                let _ = define_if_necessary(const_cstr!("\x1b\x1b[3^").as_cstr(), 2368);
+    // Recognize the keys that terminfo describes, along with the ones that some common terminals
+    // send but leave out of their terminfo entries
+    fn bind_terminfo_keys(&mut self) {
+        for (name, inp) in keys::standard_keys() {
+            self.bind_terminfo_key(&name, inp);
+        }
+
+        // Bind every modified key that terminfo describes, which we can find by name since they
+        // follow a naming convention. The names that we know of are looked up directly as well, in
+        // case ncurses can't list them.
+        for name in keys::modified_key_capability_names().chain(extended_key_names()) {
+            if let Some(inp) = keys::decode_capability_name(name.as_bytes()) {
+                self.bind_terminfo_key(&name, inp);
             }
         }
 
-        // Hackily detect if our terminal is using XTerm-style codes and add the rest if necessary
-        if ext::key_code_for(const_cstr!("\x1bOA").as_cstr()) == Ok(ncurses::KEY_UP) &&
-           ext::key_code_for(const_cstr!("\x1bOB").as_cstr()) == Ok(ncurses::KEY_DOWN) &&
-           ext::key_code_for(const_cstr!("\x1bOC").as_cstr()) == Ok(ncurses::KEY_RIGHT) &&
-           ext::key_code_for(const_cstr!("\x1bOD").as_cstr()) == Ok(ncurses::KEY_LEFT) &&
-           ext::key_code_for(const_cstr!("\x1b[1;2C").as_cstr()) == Ok(ncurses::KEY_SRIGHT) &&
-           ext::key_code_for(const_cstr!("\x1b[1;2D").as_cstr()) == Ok(ncurses::KEY_SLEFT) {
-
-            for mode in 2..=7 {
-                for &(indicator, key) in &[(b'A', 0), (b'B', 1), (b'C', 2), (b'D', 3), (b'H', 4), (b'F', 5)] {
-                    let _ = define_if_necessary(
-                        CStr::from_bytes_with_nul(&[0x1b, b'[', b'1', b';', b'1' + mode as u8, indicator, 0]).unwrap(),
-                        2300 + mode * 10 + key
-                    );
+        // Only terminals with the xterm layout of function keys have the Alt+F1 key in the range
+        if terminfo_string("kf49").is_some() {
+            for number in 13..=63 {
+                if let Some(inp) = keys::decode_extended_function_key(number) {
+                    self.bind_terminfo_key(&format!("kf{}", number), inp);
                 }
             }
+        }
+
+        // Hackily detect if our terminal is using rxvt-style codes and add the rest if necessary.
+        // Only the modified keys are checked, since the plain cursor keys depend on DECCKM.
+        let terminfo_is = |name: &str, definition: &[u8]| terminfo_string(name) == Some(definition);
+        let rxvt_style = terminfo_is("kRIT", b"\x1b[c") &&
+                         terminfo_is("kLFT", b"\x1b[d");
+        if rxvt_style {
+            self.bind_rxvt_keys();
+        }
 
-            if ext::key_code_for(const_cstr!("\x1b[3~").as_cstr()) == Ok(ncurses::KEY_DC) &&
-               ext::key_code_for(const_cstr!("\x1b[5~").as_cstr()) == Ok(ncurses::KEY_PPAGE) &&
-               ext::key_code_for(const_cstr!("\x1b[6~").as_cstr()) == Ok(ncurses::KEY_NPAGE) {
+        // The Linux console has its own codes for F1-F5 and no way of sending modifiers with special keys,
+        // except for Shift+F1 through Shift+F10, which look like F11 through F20. Its terminfo entry
+        // can't be relied on to describe these, so go by the terminal's name.
+        let term = ncurses::termname();
+        if term == "linux" || term.starts_with("linux-") {
+            self.bind_linux_keys();
+        }
+    }
+
+    fn bind_terminfo_key(&mut self, name: &str, inp: Event) {
+        if let Some(definition) = terminfo_string(name) {
+            let inp = keys::decode_keypad_definition(definition).unwrap_or(inp);
+            let _ = self.sequences.insert(definition, inp);
+        }
+    }
+
+    // Bind a key sequence that the terminal reported for one of the modified key capabilities. The
+    // terminal's answer is used in addition to whatever the local terminfo says.
+    fn bind_key_capability(&mut self, name: &[u8], definition: &[u8]) {
+        let inp = match keys::decode_capability_name(name) {
+            Some(inp) => inp,
+            None => return
+        };
+
+        // Don't let a confused terminal redefine standard keys
+        if keys::standard_keys().any(|(standard, _)| terminfo_string(&standard) == Some(definition)) {
+            return;
+        }
+        let _ = self.sequences.insert(definition, inp);
+    }
 
-                for mode in 2..=7 {
-                    for &(indicator, key) in &[(b'3', 8), (b'5', 6), (b'6', 7)] {
-                        let _ = define_if_necessary(
-                            CStr::from_bytes_with_nul(&[0x1b, b'[', indicator, b';', b'1' + mode as u8, b'~', 0]).unwrap(),
-                            2300 + mode * 10 + key
-                        );
+    // rxvt marks modified keys with a different final character instead of a parameter, e.g.
+    // `\x1bOa` for Ctrl+Up or `\x1b[5^` for Ctrl+PageUp, and most of these aren't in its terminfo
+    fn bind_rxvt_keys(&mut self) {
+        let arrows = [ncurses::KEY_UP, ncurses::KEY_DOWN, ncurses::KEY_RIGHT, ncurses::KEY_LEFT];
+        for (&letter, &arrow) in b"abcd".iter().zip(&arrows) {
+            let inp = KeyPress { modifiers: Modifiers::CTRL, key: KeyInput::Special(arrow), is_repeat: false, ambiguous: None, alternates: None };
+            let _ = self.sequences.insert(&[0x1b, b'O', letter], inp);
+        }
+        for number in 2..=34 {
+            for &suffix in b"~$^@" {
+                match keys::decode_rxvt(number, suffix) {
+                    Some(KeyPress { modifiers: Modifiers::NONE, .. }) | None => { },
+                    Some(inp) => {
+                        let _ = self.sequences.insert(format!("\x1b[{}{}", number, suffix as char).as_bytes(), inp);
                     }
                 }
             }
         }
+    }
 
-        // TODO: What about in front of, e.g., arrow keys? Generalize this.
-        // Brute-force handle the most common cases for AltSendsEscape
-        // TODO: Because we are passing in a CStr, we can't detect sequences with null bytes
-        for byte in (1..=26).chain(48..=57).chain(65..=90).chain(97..=122) {
-            let _ = define_if_necessary(CStr::from_bytes_with_nul(&[0x1b, byte as u8, 0]).unwrap(), 3000 + byte);
+    fn bind_linux_keys(&mut self) {
+        for (index, &letter) in b"ABCDE".iter().enumerate() {
+            let inp = KeyPress { modifiers: Modifiers::NONE, key: KeyInput::Special(ncurses::KEY_F1 + index as i32), is_repeat: false, ambiguous: None, alternates: None };
+            let _ = self.sequences.insert(&[0x1b, b'[', b'[', letter], inp);
         }
+        // These follow the same pattern as rxvt's unmodified keys
+        for number in 25..=34 {
+            if let Some(inp) = keys::decode_rxvt(number, b'~') {
+                let _ = self.sequences.insert(format!("\x1b[{}~", number).as_bytes(), inp);
+            }
+        }
+    }
 
-        ncurses::ll::ungetch(ncurses::KEY_RESIZE);
+    pub fn bind_sequence(&mut self, sequence: &[u8], event: Event) -> Result<(), BindError> {
+        self.sequences.insert(sequence, event).map_err(|trie::PrefixConflict| BindError::PrefixConflict)
+    }
 
-        InputStream {
-            _bracketed_paste: bracketed_paste_guard,
-            _xterm_modify_keys: xterm_modify_other_keys_guard,
-            _kitty_full_mode: kitty_full_mode_guard,
+    pub fn unbind_sequence(&mut self, sequence: &[u8]) -> Option<Event> {
+        self.sequences.remove(sequence)
+    }
 
-            extra_bound_keys,
+    // Read an input, waiting at most `timeout` milliseconds for one to arrive
+    fn read_within(&mut self, window: ncurses::WINDOW, timeout: i32) -> Option<i32> {
+        if let Some(input) = self.lookahead.pop_front() {
+            return Some(input);
+        }
+        let input = unsafe {
+            ncurses::ll::wtimeout(window, timeout);
+            let input = ncurses::ll::wgetch(window);
+            ncurses::ll::wtimeout(window, -1);
+            input
+        };
+        if input == ncurses::ERR {
+            None
+        } else {
+            Some(input)
+        }
+    }
 
-            in_progress_codepoint: 0,
-            utf8_bytes_left: 0,
-            xterm_modify_key_state: XTermModifyKeyState::Off,
-            kitty_full_mode_state: KittyFullModeState::Off
+    // Return inputs that were read ahead to the front of the queue, in their original order
+    fn unread(&mut self, consumed: Vec<i32>) {
+        for input in consumed.into_iter().rev() {
+            self.lookahead.push_front(input);
+        }
+    }
+
+    // Match a known key sequence that starts with `first`, waiting for each following byte like
+    // ncurses does for the sequences in terminfo
+    fn match_sequence(&mut self, window: ncurses::WINDOW, first: u8) -> Option<Event> {
+        let mut sequence = vec![first];
+        let mut consumed = Vec::new();
+        loop {
+            match self.sequences.find(&sequence) {
+                trie::Match::Complete(inp) => return Some(inp.clone()),
+                trie::Match::Partial => { },
+                trie::Match::None => break
+            }
+            match self.read_within(window, self.escape_delay) {
+                Some(input @ 0..=255) => {
+                    consumed.push(input);
+                    sequence.push(input as u8);
+                },
+                Some(input) => {
+                    consumed.push(input);
+                    break;
+                },
+                None => break
+            }
         }
+        self.unread(consumed);
+        None
+    }
+
+    // Read the next byte of what may be a control sequence, recording it so that it can be pushed
+    // back if the sequence turns out to be something else
+    fn read_sequence_byte(&mut self, window: ncurses::WINDOW, consumed: &mut Vec<i32>) -> Option<u8> {
+        // Don't wait for the first two bytes after the escape, since those could be from the
+        // user pressing Escape or Alt+[ instead of from the terminal
+        let timeout = if consumed.len() < 2 { 0 } else { REPORT_TIMEOUT };
+        let input = self.read_within(window, timeout)?;
+        consumed.push(input);
+        if input < 256 {
+            Some(input as u8)
+        } else {
+            None
+        }
+    }
+
+    // Check whether the bytes after an escape form a terminal report, as opposed to a key
+    fn parse_report(&mut self, window: ncurses::WINDOW) -> Option<Report> {
+        let mut consumed = Vec::new();
+        let clipboard_limit = self.clipboard_limit;
+        let report = match self.read_sequence_byte(window, &mut consumed) {
+            Some(b'[') => match csi::parse(|| self.read_sequence_byte(window, &mut consumed)) {
+                Some(sequence) => self.decode_control_sequence(window, &sequence, &mut consumed),
+                None => None
+            },
+            Some(b']') => osc::parse(|| self.read_sequence_byte(window, &mut consumed), |command| {
+                if command == 52 {
+                    // Base64 turns every 3 bytes, rounded up, into 4
+                    clipboard_limit.map_or(0, |limit| {
+                        (limit.saturating_add(2) / 3).saturating_mul(4).saturating_add(MAX_SELECTION_LENGTH)
+                    })
+                } else {
+                    MAX_REPORT_LENGTH
+                }
+            }).and_then(|command| self.decode_operating_system_command(&command)),
+            Some(b'P') if self.pending_capability_queries > 0 => dcs::parse(|| self.read_sequence_byte(window, &mut consumed), MAX_REPORT_LENGTH)
+                .and_then(|string| self.decode_device_control_string(&string)),
+            Some(b'_') => osc::parse_string(|| self.read_sequence_byte(window, &mut consumed), MAX_REPORT_LENGTH)
+                .and_then(|(data, truncated)| if truncated { None } else { kitty::decode(&data) })
+                .map(Report::Event),
+            _ => None
+        };
+
+        if report.is_none() {
+            self.unread(consumed);
+        }
+        report
+    }
+
+    // Decode a key that terminfo doesn't list but that is sent following xterm's conventions
+    fn parse_key_sequence(&mut self, window: ncurses::WINDOW) -> Option<Event> {
+        let mut consumed = Vec::new();
+        let inp = match self.read_sequence_byte(window, &mut consumed) {
+            Some(b'[') => csi::parse(|| self.read_sequence_byte(window, &mut consumed)).and_then(|sequence| keys::decode_csi(&sequence)),
+            Some(b'O') => csi::parse(|| self.read_sequence_byte(window, &mut consumed)).and_then(|sequence| keys::decode_ss3(&sequence)),
+            _ => None
+        };
+
+        if inp.is_none() {
+            self.unread(consumed);
+        }
+        inp
+    }
+
+    // Collect a control sequence or control string that we don't understand, so that it can be
+    // passed on whole instead of as a series of unrelated keys
+    fn parse_unknown_sequence(&mut self, window: ncurses::WINDOW) -> Option<Event> {
+        let mut consumed = Vec::new();
+        let complete = match self.read_sequence_byte(window, &mut consumed) {
+            Some(b'[') | Some(b'O') => csi::skip(|| self.read_sequence_byte(window, &mut consumed)),
+            Some(b']') | Some(b'P') | Some(b'_') => osc::parse_string(|| self.read_sequence_byte(window, &mut consumed), 0).map(|_| ()),
+            _ => None
+        };
+
+        match complete {
+            Some(()) => Some(UnknownSequence(std::iter::once(0x1b).chain(consumed.into_iter().map(|input| input as u8)).collect())),
+            None => {
+                self.unread(consumed);
+                None
+            }
+        }
+    }
+
+    fn decode_operating_system_command(&mut self, command: &osc::OperatingSystemCommand) -> Option<Report> {
+        if command.command == 52 {
+            // Only pass on clipboard contents that were asked for and fit in the requested size
+            let limit = match self.clipboard_limit.take() {
+                Some(limit) => limit,
+                None => return Some(Report::Ignored)
+            };
+            if command.truncated {
+                return Some(Report::Event(Clipboard(Err(ClipboardError::TooLarge))));
+            }
+            let contents = command.data.iter().position(|&byte| byte == b';')
+                .and_then(|separator| osc::decode_base64(&command.data[separator + 1..]));
+            return Some(Report::Event(Clipboard(match contents {
+                Some(contents) if contents.len() <= limit => Ok(String::from_utf8_lossy(&contents).into_owned()),
+                Some(_) => Err(ClipboardError::TooLarge),
+                None => Err(ClipboardError::Malformed)
+            })));
+        }
+
+        if command.truncated {
+            return None;
+        }
+        let event = match command.command {
+            10 => ColorReport { color: TerminalColor::Foreground, rgb: osc::parse_rgb(&command.data)? },
+            11 => ColorReport { color: TerminalColor::Background, rgb: osc::parse_rgb(&command.data)? },
+            4 => {
+                let separator = command.data.iter().position(|&byte| byte == b';')?;
+                let index = std::str::from_utf8(&command.data[..separator]).ok()?.parse().ok()?;
+                ColorReport { color: TerminalColor::Palette(index), rgb: osc::parse_rgb(&command.data[separator + 1..])? }
+            },
+            _ => return None
+        };
+        Some(Report::Event(event))
+    }
+
+    fn decode_device_control_string(&mut self, string: &dcs::DeviceControlString) -> Option<Report> {
+        // XTGETTCAP replies look like `1+r<name>=<value>` on success and `0+r<name>` on failure
+        let found = match (string.header.private_marker, &string.header.parameters[..], &string.header.intermediates[..], string.header.final_byte) {
+            (None, &[found], b"+", b'r') if found <= 1 && !string.truncated => found == 1,
+            _ => return None
+        };
+
+        // Decode every entry before acting on any, so that a malformed reply is left alone entirely
+        let entries = string.data.split(|&byte| byte == b';').map(|entry| {
+            let mut parts = entry.splitn(2, |&byte| byte == b'=');
+            let name = dcs::decode_hex(parts.next()?)?;
+            let value = match parts.next() {
+                Some(value) if found => Some(dcs::decode_hex(value)?),
+                _ => None
+            };
+            Some((name, value))
+        }).collect::<Option<Vec<_>>>()?;
+
+        let mut event = None;
+        for (name, value) in entries {
+            if let Some(index) = self.requested_capabilities.iter().position(|requested| *requested == name) {
+                self.requested_capabilities.remove(index);
+                if event.is_none() {
+                    event = Some(TerminalCapability {
+                        name: String::from_utf8_lossy(&name).into_owned(),
+                        value: value.clone()
+                    });
+                }
+            }
+            if let Some(value) = value {
+                self.bind_key_capability(&name, &value);
+            }
+        }
+
+        Some(match event {
+            Some(event) => Report::Event(event),
+            None => Report::Ignored
+        })
+    }
+
+    fn decode_control_sequence(&mut self, window: ncurses::WINDOW, sequence: &csi::ControlSequence, consumed: &mut Vec<i32>) -> Option<Report> {
+        if sequence.has_subparameters() {
+            return None;
+        }
+        let event = match (sequence.private_marker, &sequence.parameters[..], &sequence.intermediates[..], sequence.final_byte) {
+            (None, &[48, height, width, ref pixels @ ..], b"", b't') if pixels.len() == 2 || pixels.is_empty() => {
+                // Keep ncurses in sync, since it may never see a SIGWINCH for this change. It will
+                // queue up a KEY_RESIZE in response, which we ignore now that we have in-band reports.
+                self.received_in_band_resize = true;
+                let mut current_height = 0;
+                let mut current_width = 0;
+                ncurses::getmaxyx(window, &mut current_height, &mut current_width);
+                if current_height as u32 != height || current_width as u32 != width {
+                    ncurses::resizeterm(height as i32, width as i32);
+                }
+                Resize {
+                    width,
+                    height,
+                    pixel_width: pixels.get(1).cloned(),
+                    pixel_height: pixels.first().cloned()
+                }
+            },
+            (None, &[row, col], b"", b'R') => {
+                // A report on the first row is indistinguishable from F3 with modifiers, so only
+                // treat it as a report if we asked for one.
+                if row == 1 && self.pending_cursor_reports == 0 {
+                    return None;
+                }
+                self.pending_cursor_reports = self.pending_cursor_reports.saturating_sub(1);
+                CursorPosition {
+                    row: row.saturating_sub(1),
+                    col: col.saturating_sub(1)
+                }
+            },
+            // The primary device attributes that we ask for after XTGETTCAP queries
+            (Some(b'?'), _, b"", b'c') if self.pending_capability_queries > 0 => {
+                self.pending_capability_queries -= 1;
+                if self.pending_capability_queries == 0 {
+                    // Whatever is left was never answered
+                    self.requested_capabilities.clear();
+                }
+                return Some(Report::Ignored);
+            },
+            (Some(b'?'), &[997, 1], b"", b'n') => ColorSchemeChanged { dark: true },
+            (Some(b'?'), &[997, 2], b"", b'n') => ColorSchemeChanged { dark: false },
+            (None, &[200], b"", b'~') => PasteBegin,
+            (None, &[201], b"", b'~') => PasteEnd,
+            (None, &[], b"", b'I') => FocusGained,
+            (None, &[], b"", b'O') => FocusLost,
+            (Some(b'<'), _, b"", b'M') | (Some(b'<'), _, b"", b'm') => {
+                return Some(mouse::decode_sgr(sequence).map_or(Report::Ignored, Report::Event));
+            },
+            (None, &[], b"", b'M') => {
+                // The X10 encoding puts the button and position in the three raw bytes that follow
+                let mut bytes = [0; 3];
+                for byte in &mut bytes {
+                    *byte = self.read_sequence_byte(window, consumed)?;
+                }
+                return Some(mouse::decode_x10(bytes, &mut self.pressed_mouse_button).map_or(Report::Ignored, Report::Event));
+            },
+            _ => return None
+        };
+        Some(Report::Event(event))
     }
 
     pub fn next_event(&mut self, window: ncurses::WINDOW) -> Result<Event, ()> {
@@ -320,14 +836,88 @@ impl InputStream {
         const NONE: Modifiers = crate::Modifiers::NONE;
         const CTRL: Modifiers = crate::Modifiers::CTRL;
         const ALT: Modifiers = crate::Modifiers::ALT;
-        const SHIFT: Modifiers = crate::Modifiers::SHIFT;
+
+        if let Some(event) = self.queued_event.take() {
+            return Ok(event);
+        }
 
         loop {
-            let curses_input = unsafe { ncurses::ll::wgetch(window) };
+            let curses_input = match self.lookahead.pop_front() {
+                Some(input) => input,
+                None => unsafe { ncurses::ll::wgetch(window) }
+            };
             if curses_input == ncurses::ERR {
                 return Err(());
             }
 
+            if self.high_bit_input == HighBitInput::Meta && (0x80..0x100).contains(&curses_input) {
+                return Ok(keys::decode_meta(curses_input as u8 & 0x7f, self.control_character_style));
+            }
+
+            if curses_input == 0x1b && self.utf8_bytes_left == 0 {
+                // Give the rest of an escape sequence a moment to arrive. If nothing does, this
+                // was just the Escape key.
+                match self.read_within(window, self.escape_delay) {
+                    Some(next_input) => self.lookahead.push_front(next_input),
+                    None => {
+                        let inp = KeyPress { modifiers: NONE, key: Codepoint('\u{1b}'), is_repeat: false, ambiguous: None, alternates: None };
+                        return Ok(keys::mark_ambiguous(inp, 0x1b, self.control_character_style));
+                    }
+                }
+
+                match self.parse_report(window) {
+                    Some(Report::Event(event)) => return Ok(event),
+                    Some(Report::Ignored) => continue,
+                    None => { }
+                }
+                if let Some(inp) = self.match_sequence(window, 0x1b) {
+                    return Ok(inp);
+                }
+                if let Some(inp) = self.parse_key_sequence(window) {
+                    return Ok(inp);
+                }
+                if let Some(event) = self.parse_unknown_sequence(window) {
+                    return Ok(event);
+                }
+
+                // AltSendsEscape: an escape that arrives together with a complete key means that
+                // key was pressed with Alt. Anything else gets delivered after the escape itself.
+                if let Some(next_input) = self.read_within(window, 0) {
+                    // Alt+Enter sends a carriage return, so a newline must come from Ctrl+Alt+J
+                    if next_input == b'\n' as i32 {
+                        return Ok(KeyPress { modifiers: CTRL | ALT, key: Codepoint('j'), is_repeat: false, ambiguous: None, alternates: None });
+                    }
+                    // Alt+Escape is decoded here instead of in a nested call, so that a run of escapes,
+                    // e.g. from holding Escape down, gives one event per pair without nesting deeper.
+                    // An escape that starts something else, as in rxvt's `\x1b\x1b[A` for Alt+Up, is
+                    // still left to the nested call.
+                    if next_input == 0x1b {
+                        let following = self.read_within(window, 0);
+                        if let Some(following) = following {
+                            self.lookahead.push_front(following);
+                        }
+                        if following.is_none() || following == Some(0x1b) {
+                            return Ok(keys::decode_meta(0x1b, self.control_character_style));
+                        }
+                    }
+                    self.lookahead.push_front(next_input);
+                    match self.next_event(window)? {
+                        KeyPress { modifiers, key, is_repeat, ambiguous, alternates } => {
+                            let ambiguous = ambiguous.map(|(modifiers, key)| (modifiers | ALT, key));
+                            return Ok(KeyPress { modifiers: modifiers | ALT, key, is_repeat, ambiguous, alternates });
+                        },
+                        event => self.queued_event = Some(event)
+                    }
+                }
+            } else if (0..256).contains(&curses_input) && self.utf8_bytes_left == 0 {
+                // Keys like Backspace may be described in terminfo as a single control character
+                let byte = curses_input as u8;
+                let single_byte = matches!(self.sequences.find(&[byte]), trie::Match::Complete(_));
+                if let Some(inp) = self.match_sequence(window, byte) {
+                    return Ok(if single_byte { keys::mark_ambiguous(inp, byte, self.control_character_style) } else { inp });
+                }
+            }
+
             let input;
             // We need to parse utf8.
             if curses_input < 256 {
@@ -347,18 +937,23 @@ impl InputStream {
                         self.utf8_bytes_left = 3;
                         self.in_progress_codepoint = (byte & 0x07) as u32;
                     } else {
-                        return Ok(KeyPress { modifiers: NONE, key: Byte(byte), is_repeat: false });
+                        return Ok(KeyPress { modifiers: NONE, key: Byte(byte), is_repeat: false, ambiguous: None, alternates: None });
                     }
                 } else if byte >> 6 == 0b10 {
                     // Continuation bytes
                     self.utf8_bytes_left -= 1;
                     self.in_progress_codepoint = (self.in_progress_codepoint << 6) | ((byte & 0x3f) as u32);
                 } else {
-                    return Ok(KeyPress { modifiers: NONE, key: Byte(byte), is_repeat: false });
+                    // The character was cut short, so start over with the next one
+                    self.utf8_bytes_left = 0;
+                    return Ok(KeyPress { modifiers: NONE, key: Byte(byte), is_repeat: false, ambiguous: None, alternates: None });
                 }
                 if self.utf8_bytes_left == 0 {
-                    // FIXME: This should not crash
-                    input = Codepoint(std::char::from_u32(self.in_progress_codepoint).expect("BUG: Bad char cast"));
+                    // Surrogates and anything above U+10FFFF can be encoded but aren't characters
+                    match std::char::from_u32(self.in_progress_codepoint) {
+                        Some(chr) => input = Codepoint(chr),
+                        None => return Ok(KeyPress { modifiers: NONE, key: Byte(byte), is_repeat: false, ambiguous: None, alternates: None })
+                    }
                 } else {
                     continue;
                 }
@@ -366,389 +961,41 @@ impl InputStream {
                 input = Special(curses_input);
             }
 
-            // Translate keys bound to non-standard terminfo entries
-            if let Special(code) = input {
-                for &(possible_code, possible_inp) in &self.extra_bound_keys {
-                    if possible_code == code {
-                        return Ok(possible_inp);
-                    }
-                }
-            }
-
             // Translate various known special keys to a decomposed form
-            match input {
+            let (modifiers, key) = match input {
                 // Non-key inputs
                 Special(ncurses::KEY_RESIZE) => {
+                    if self.received_in_band_resize {
+                        continue;
+                    }
                     let mut height = 0;
                     let mut width = 0;
                     ncurses::getmaxyx(window, &mut height, &mut width);
                     return Ok(Resize {
                         width: width as u32,
-                        height: height as u32
-                    });
-                },
-                Special(ncurses::KEY_MOUSE) => {
-                    let mut event = ncurses::ll::MEVENT {
-                        id: 0,
-                        x: 0,
-                        y: 0,
-                        z: 0,
-                        bstate: 0
-                    };
-                    if ncurses::getmouse(&mut event) != ncurses::OK {
-                        return Err(());
-                    }
-                    return Ok(Mouse {
-                        device_id: event.id as u16,
-                        x: event.x as u32,
-                        y: event.y as u32,
-                        buttons: event.bstate & !((ncurses::BUTTON_CTRL | ncurses::BUTTON_ALT | ncurses::BUTTON_SHIFT) as u32),
-                        modifiers: if event.bstate & (ncurses::BUTTON_CTRL as u32)  != 0 { CTRL  } else { NONE }
-                                 | if event.bstate & (ncurses::BUTTON_ALT as u32)   != 0 { ALT   } else { NONE }
-                                 | if event.bstate & (ncurses::BUTTON_SHIFT as u32) != 0 { SHIFT } else { NONE }
+                        height: height as u32,
+                        pixel_width: None,
+                        pixel_height: None
                     });
-                }
-                Special(2000) => return Ok(PasteBegin),
-                Special(2001) => return Ok(PasteEnd),
-                // Shifted standard keys
-                Special(ncurses::KEY_SLEFT)  => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_LEFT), is_repeat: false }),
-                Special(ncurses::KEY_SRIGHT) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_RIGHT), is_repeat: false }),
-                Special(ncurses::KEY_SR)     => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_UP), is_repeat: false }),
-                Special(ncurses::KEY_SF)     => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_DOWN), is_repeat: false }),
-                Special(ncurses::KEY_SHOME)  => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_HOME), is_repeat: false }),
-                Special(ncurses::KEY_SEND)   => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_END), is_repeat: false }),
-                Special(ncurses::KEY_SDC)    => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_DC), is_repeat: false }),
-                Special(ncurses::KEY_BTAB)   => return Ok(KeyPress { modifiers: SHIFT, key: Codepoint('\t'), is_repeat: false }),
-                // Shifted uncommon keys
-                Special(ncurses::KEY_SBEG) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_BEG), is_repeat: false }),
-                Special(ncurses::KEY_SCANCEL) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_CANCEL), is_repeat: false }),
-                Special(ncurses::KEY_SCOMMAND) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_COMMAND), is_repeat: false }),
-                Special(ncurses::KEY_SCOPY) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_COPY), is_repeat: false }),
-                Special(ncurses::KEY_SCREATE) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_CREATE), is_repeat: false }),
-                Special(ncurses::KEY_SDL) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_DL), is_repeat: false }),
-                Special(ncurses::KEY_SEOL) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_EOL), is_repeat: false }),
-                Special(ncurses::KEY_SEXIT) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_EXIT), is_repeat: false }),
-                Special(ncurses::KEY_SFIND) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_FIND), is_repeat: false }),
-                Special(ncurses::KEY_SHELP) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_HELP), is_repeat: false }),
-                Special(ncurses::KEY_SIC) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_IC), is_repeat: false }),
-                Special(ncurses::KEY_SMESSAGE) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_MESSAGE), is_repeat: false }),
-                Special(ncurses::KEY_SMOVE) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_MOVE), is_repeat: false }),
-                Special(ncurses::KEY_SNEXT) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_NEXT), is_repeat: false }),
-                Special(ncurses::KEY_SOPTIONS) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_OPTIONS), is_repeat: false }),
-                Special(ncurses::KEY_SPREVIOUS) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_PREVIOUS), is_repeat: false }),
-                Special(ncurses::KEY_SPRINT) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_PRINT), is_repeat: false }),
-                Special(ncurses::KEY_SREDO) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_REDO), is_repeat: false }),
-                Special(ncurses::KEY_SREPLACE) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_REPLACE), is_repeat: false }),
-                Special(ncurses::KEY_SRSUME) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_RESUME), is_repeat: false }),
-                Special(ncurses::KEY_SSAVE) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_SAVE), is_repeat: false }),
-                Special(ncurses::KEY_SUNDO) => return Ok(KeyPress { modifiers: SHIFT, key: Special(ncurses::KEY_UNDO), is_repeat: false }),
-                // Ctrl+Z triggers a suspend
-                Special(ncurses::KEY_SUSPEND) => return Ok(KeyPress { modifiers: CTRL, key: Codepoint('z'), is_repeat: false }),
-                Special(ncurses::KEY_SSUSPEND) => return Ok(KeyPress { modifiers: CTRL | SHIFT, key: Codepoint('z'), is_repeat: false }),
-                // The DEL and BACKSPACE have different meanings, but since they are inconsistently assigned, we unify them into one code
-                Codepoint('\u{7f}') => return Ok(KeyPress { modifiers: NONE, key: Special(ncurses::KEY_BACKSPACE), is_repeat: false }),
-                // Both Ctrl+` and Ctrl+Space generate a null bytem but Ctrl+Space seems much more common
-                Codepoint('\0') => return Ok(KeyPress { modifiers: CTRL, key: Codepoint(' '), is_repeat: false }),
-                // Assume that control characters aren't from actual typing and are instead generated by Ctrl + a printable character
-                Codepoint(chr) if (chr as u32) > 0 && (chr as u32) < 27 && chr != '\t' && chr != '\n' && chr != '\u{8}'
-                    => return Ok(KeyPress { modifiers: CTRL, key: Codepoint(std::char::from_u32(chr as u32 + 96).unwrap()), is_repeat: false }),
-                Codepoint(chr) if (chr as u32) > 128 && (chr as u32) < 155 // TODO: Consider whitelist? Cancel is sometimes used for Backspace
-                    => return Ok(KeyPress { modifiers: CTRL | ALT, key: Codepoint(std::char::from_u32(chr as u32 - 32).unwrap()), is_repeat: false }),
-                // AltSendsEscape + either a control character (assumed to be from Ctrl) or a printable character
-                Special(code @ 3001..=3255) => if code < 3027 && code != 3008 && code != 3009 && code != 3013 {
-                    // Note that we actually treat \n as a control code originating from Ctrl+j, unlike above; this is because the actual Enter
-                    // key will be sent as a carriage return.
-                    return Ok(KeyPress { modifiers: CTRL | ALT, key: Codepoint(std::char::from_u32(code as u32 - 3000 + 96).unwrap()), is_repeat: false });
-                } else if code == 3013 {
-                    // The Enter key at a terminal actually sends \r, not \n. Normally, either the ICRNL termios flag translates it or
-                    // ncurses translates it, but we are handling it manually, so we need to translate ourselves.
-                    return Ok(KeyPress { modifiers: ALT, key: Codepoint('\n'), is_repeat: false });
-                } else {
-                    return Ok(KeyPress { modifiers: ALT, key: Codepoint(std::char::from_u32(code as u32 - 3000).unwrap()), is_repeat: false });
-                },
-                // XTerm-style modified keys that weren't in the Terminfo
-                Special(code @ 2300..=2399) => {
-                    let base_code = code - 2300;
-                    let modifiers = Modifiers((base_code / 10) as u8);
-                    match base_code % 10 {
-                        0 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_UP), is_repeat: false }),
-                        1 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_DOWN), is_repeat: false }),
-                        2 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_RIGHT), is_repeat: false }),
-                        3 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_LEFT), is_repeat: false }),
-                        4 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_HOME), is_repeat: false }),
-                        5 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_END), is_repeat: false }),
-                        6 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_PPAGE), is_repeat: false }),
-                        7 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_NPAGE), is_repeat: false }),
-                        8 => return Ok(KeyPress { modifiers, key: Special(ncurses::KEY_DC), is_repeat: false }),
-                        _ => { }
-                    }
-                },
-                _ => { }
-            }
-
-            // Handle XTerm's modifyOtherKeys extension, parsing manually
-            if let Special(2100) = input {
-                self.xterm_modify_key_state = XTermModifyKeyState::ParsingMode(0);
-                continue;
-            }
-            match self.xterm_modify_key_state {
-                XTermModifyKeyState::Off => { },
-                XTermModifyKeyState::ParsingMode(mode_so_far) => {
-                    if let Codepoint(chr) = input {
-                        if let Some(digit) = chr.to_digit(10) {
-                            self.xterm_modify_key_state = XTermModifyKeyState::ParsingMode(mode_so_far * 10 + digit);
-                            continue;
-                        } else if chr == ';' {
-                            self.xterm_modify_key_state = XTermModifyKeyState::ParsingChar(mode_so_far, 0);
-                            continue;
-                        }
-                    }
                 },
-                XTermModifyKeyState::ParsingChar(mode, char_so_far) => {
-                    if let Codepoint(chr) = input {
-                        if let Some(digit) = chr.to_digit(10) {
-                            self.xterm_modify_key_state = XTermModifyKeyState::ParsingChar(mode, char_so_far * 10 + digit);
-                            continue;
-                        } else if chr == '~' {
-                            self.xterm_modify_key_state = XTermModifyKeyState::Off;
-                            if 1 <= mode {
-                                // FIXME: This should not crash
-                                return Ok(KeyPress { modifiers: Modifiers((mode as u8) - 1), key: Codepoint(std::char::from_u32(char_so_far).unwrap()), is_repeat: false });
-                            } else {
-                                eprintln!("0 mode?");
-                                continue;
-                            }
-                        }
-                    }
-                }
-            }
-
-            // Handle Kitty's full mode extension, parsing manually
-            if let Special(2200) = input {
-                self.kitty_full_mode_state = KittyFullModeState::ParsingType;
-                continue;
-            }
-            match self.kitty_full_mode_state {
-                KittyFullModeState::Off => { },
-                KittyFullModeState::ParsingType => match input {
-                    Codepoint('p') => {
-                        self.kitty_full_mode_state = KittyFullModeState::ParsingModifiers(KeyType::Press);
-                        continue;
-                    },
-                    Codepoint('r') => {
-                        self.kitty_full_mode_state = KittyFullModeState::ParsingModifiers(KeyType::Release);
-                        continue;
-                    },
-                    Codepoint('t') => {
-                        self.kitty_full_mode_state = KittyFullModeState::ParsingModifiers(KeyType::Repeat);
-                        continue;
-                    },
-                    _ => { }
+                // Assume that control characters aren't from actual typing and are instead generated by Ctrl + a printable character,
+                // except for the ones that keys like Enter send. Note that the Enter key at a terminal actually sends \r, not \n.
+                // Normally, either the ICRNL termios flag translates it or ncurses translates it, but we are handling it manually.
+                Codepoint(chr) if (chr as u32) < 0x20 || chr == '\u{7f}' => {
+                    keys::decode_control_character(chr as u8, self.control_character_style).unwrap()
                 },
-                KittyFullModeState::ParsingModifiers(key_type) => {
-                    if let Codepoint(chr) = input {
-                        // Decode base 64
-                        let decoded = if ('A'..='Z').contains(&chr) {
-                            Some(chr as u32 - 'A' as u32)
-                        } else if ('a'..='z').contains(&chr) {
-                            Some(chr as u32 - 'a' as u32 + 26)
-                        } else if ('0'..='9').contains(&chr) {
-                            Some(chr as u32 - '0' as u32 + 52)
-                        } else if chr == '+' {
-                            Some(62)
-                        } else if chr == '/' {
-                            Some(63)
-                        } else {
-                            None
-                        };
-                        if let Some(mode) = decoded {
-                            self.kitty_full_mode_state = KittyFullModeState::ParsingKey(key_type, mode as u8, 0);
-                            continue;
-                        }
-                    }
+                // The same with the high bit set, which is how xterm sends Alt in 8-bit meta mode with UTF-8 enabled
+                Codepoint(chr) if (0x80..0xa0).contains(&(chr as u32)) => {
+                    return Ok(keys::decode_meta(chr as u8 - 0x80, self.control_character_style));
                 },
-                KittyFullModeState::ParsingKey(key_type, mode, key_so_far) => {
-                    if let Codepoint(chr) = input {
-                        let decoded = if ('A'..='Z').contains(&chr) {
-                            Some(chr as u32 - 'A' as u32)
-                        } else if ('a'..='z').contains(&chr) {
-                            Some(chr as u32 - 'a' as u32 + 26)
-                        } else if ('0'..='9').contains(&chr) {
-                            Some(chr as u32 - '0' as u32 + 52)
-                        } else {
-                            ".-:+=^!/*?&<>()[]{}@%$#".chars().position(|c| c == chr).map(|i| i as u32 + 62)
-                        };
-                        if let Some(value) = decoded {
-                            self.kitty_full_mode_state = KittyFullModeState::ParsingKey(key_type, mode, key_so_far * 85 + value);
-                            continue;
-                        }
-                    } else if let Special(2201) = input {
-                        self.kitty_full_mode_state = KittyFullModeState::Off;
-                        let modifiers = Modifiers(mode);
-                        // FIXME: Kitty does not provide an indication of the correct capital version of a shifted
-                        // key; decide on a strategy for dealing with that since keyboard layouts aren't always consistent
-                        // Note that without Ctrl or Alt, this protocol is not used, so the capital variants are available
-                        let translated = match key_so_far {
-                            0 => Codepoint(' '),
-                            1 if modifiers & SHIFT == NONE => Codepoint('\''),
-                            2 if modifiers & SHIFT == NONE => Codepoint(','),
-                            3 if modifiers & SHIFT == NONE => Codepoint('-'),
-                            4 if modifiers & SHIFT == NONE => Codepoint('.'),
-                            5 if modifiers & SHIFT == NONE => Codepoint('/'),
-                            6..=15 if modifiers & SHIFT == NONE => {
-                                Codepoint(std::char::from_u32('0' as u32 + key_so_far as u32 - 6).unwrap())
-                            },
-                            16 if modifiers & SHIFT == NONE => Codepoint(';'),
-                            17 if modifiers & SHIFT == NONE => Codepoint('='),
-                            18..=43 => if modifiers & SHIFT == NONE { // If shift, capitalize the letter
-                                Codepoint(std::char::from_u32('a' as u32 + key_so_far as u32 - 18).unwrap())
-                            } else {
-                                Codepoint(std::char::from_u32('A' as u32 + key_so_far as u32 - 18).unwrap())
-                            },
-                            44 if modifiers & SHIFT == NONE => Codepoint('['),
-                            45 if modifiers & SHIFT == NONE => Codepoint('\\'),
-                            46 if modifiers & SHIFT == NONE => Codepoint(']'),
-                            47 if modifiers & SHIFT == NONE => Codepoint('`'),
-                            50 => Codepoint('\u{1b}'), // Escape
-                            51 => Codepoint('\n'),
-                            52 => Codepoint('\t'),
-                            53 => Special(ncurses::KEY_BACKSPACE),
-                            54 => Special(ncurses::KEY_IC),
-                            55 => Special(ncurses::KEY_DC),
-                            56 => Special(ncurses::KEY_RIGHT),
-                            57 => Special(ncurses::KEY_LEFT),
-                            58 => Special(ncurses::KEY_DOWN),
-                            59 => Special(ncurses::KEY_UP),
-                            60 => Special(ncurses::KEY_PPAGE),
-                            61 => Special(ncurses::KEY_NPAGE),
-                            62 => Special(ncurses::KEY_HOME),
-                            63 => Special(ncurses::KEY_END),
-                            69 => Special(ncurses::KEY_F1),
-                            70 => Special(ncurses::KEY_F2),
-                            71 => Special(ncurses::KEY_F3),
-                            72 => Special(ncurses::KEY_F4),
-                            73 => Special(ncurses::KEY_F5),
-                            74 => Special(ncurses::KEY_F6),
-                            75 => Special(ncurses::KEY_F7),
-                            76 => Special(ncurses::KEY_F8),
-                            77 => Special(ncurses::KEY_F9),
-                            78 => Special(ncurses::KEY_F10),
-                            79 => Special(ncurses::KEY_F11),
-                            80 => Special(ncurses::KEY_F12),
-                            // TODO: mark keypad inputs differently
-                            // TODO: Maybe don't assume that NumLock is on? Also depending on
-                            //   settings Shift can toggle NumLock
-                            94..=103 if modifiers & SHIFT == NONE => {
-                                Codepoint(std::char::from_u32('0' as u32 + key_so_far as u32 - 94).unwrap())
-                            },
-                            104 => Codepoint('.'),
-                            105 => Codepoint('/'),
-                            106 => Codepoint('*'),
-                            107 => Codepoint('-'),
-                            108 => Codepoint('+'),
-                            109 => Codepoint('\n'),
-                            110 => Codepoint('='),
-                            // End of keypad inputs
-                            119 if modifiers & SHIFT == NONE => Codepoint('+'),
-                            120 if modifiers & SHIFT == NONE => Codepoint('_'),
-                            122 if modifiers & SHIFT == NONE => Codepoint('!'),
-                            123 if modifiers & SHIFT == NONE => Codepoint('"'),
-                            124 if modifiers & SHIFT == NONE => Codepoint('#'),
-                            125 if modifiers & SHIFT == NONE => Codepoint('$'),
-                            126 if modifiers & SHIFT == NONE => Codepoint('&'),
-                            127 if modifiers & SHIFT == NONE => Codepoint('('),
-                            128 if modifiers & SHIFT == NONE => Codepoint(')'),
-                            129 if modifiers & SHIFT == NONE => Codepoint(':'),
-                            130 if modifiers & SHIFT == NONE => Codepoint('<'),
-                            131 if modifiers & SHIFT == NONE => Codepoint('>'),
-                            132 if modifiers & SHIFT == NONE => Codepoint('@'),
-                            // FIXME: see ù
-                            135 if modifiers & SHIFT == NONE => Codepoint('à'), // else Codepoint('À')
-                            136 => if modifiers & SHIFT == NONE {
-                                Codepoint('ä')
-                            } else {
-                                Codepoint('Ä')
-                            },
-                            137 => if modifiers & SHIFT == NONE {
-                                Codepoint('å')
-                            } else {
-                                Codepoint('Å')
-                            },
-                            138 => if modifiers & SHIFT == NONE {
-                                Codepoint('æ')
-                            } else {
-                                Codepoint('Æ')
-                            },
-                            // FIXME: see ù
-                            139 if modifiers & SHIFT == NONE => Codepoint('ç'), // else Codepoint('Ç')
-                            // FIXME: see ù
-                            140 if modifiers & SHIFT == NONE => Codepoint('è'), // else Codepoint('È')
-                            // FIXME: see ù
-                            141 if modifiers & SHIFT == NONE => Codepoint('é'), // else Codepoint('É')
-                            142 => if modifiers & SHIFT == NONE {
-                                Codepoint('ì')
-                            } else {
-                                Codepoint('Ì')
-                            },
-                            143 => if modifiers & SHIFT == NONE {
-                                Codepoint('ñ')
-                            } else {
-                                Codepoint('Ñ')
-                            },
-                            144 => if modifiers & SHIFT == NONE {
-                                Codepoint('ò')
-                            } else {
-                                Codepoint('Ò')
-                            },
-                            145 => if modifiers & SHIFT == NONE {
-                                Codepoint('ö')
-                            } else {
-                                Codepoint('Ö')
-                            },
-                            146 => if modifiers & SHIFT == NONE {
-                                Codepoint('ø')
-                            } else {
-                                Codepoint('Ø')
-                            },
-                            // FIXME
-                            // We could easily capitalize this on Shift, but it is unclear if we
-                            // should; the AZERTY layout is probably the most likely source of a
-                            // 'ù' key press, and the Shifted version of that key is %, not Ù.
-                            // All of this is somewhat broken anyway with AltGr, since that
-                            // isn't sent at all.
-                            // Note that this is only a problem for Alt+Shift and Ctrl+Shift.
-                            147 if modifiers & SHIFT == NONE => Codepoint('ù'),
-                            148 => if modifiers & SHIFT == NONE {
-                                Codepoint('ü')
-                            } else {
-                                Codepoint('Ü')
-                            },
-                            // For now, the sharp S does not typically share a key with its capital
-                            149 if modifiers & SHIFT == NONE => Codepoint('ß'),
-                            150..=181 => if modifiers & SHIFT == NONE { // Cyrillic characters
-                                Codepoint(std::char::from_u32('а' as u32 + key_so_far as u32 - 150).unwrap())
-                            } else {
-                                Codepoint(std::char::from_u32('А' as u32 + key_so_far as u32 - 150).unwrap())
-                            },
-                            // Ie with grave (ѐ) is skipped
-                            182 => if modifiers & SHIFT == NONE {
-                                Codepoint('ё')
-                            } else {
-                                Codepoint('Ё')
-                            },
-                            183 if modifiers & SHIFT == NONE => Codepoint('\u{0302}'), // Circumflex
-                            _ => Special(key_so_far as i32 + 600)
-                        };
-                        return Ok(match key_type {
-                            KeyType::Press   => KeyPress { modifiers, key: translated, is_repeat: false },
-                            KeyType::Repeat  => KeyPress { modifiers, key: translated, is_repeat: true },
-                            KeyType::Release => KeyRelease { modifiers, key: translated },
-                        });
-                    }
-                }
-            }
-
-            return Ok(KeyPress { modifiers: NONE, key: input, is_repeat: false })
+                _ => (NONE, input)
+            };
+
+            let ambiguous = match input {
+                Codepoint(chr) if chr.is_ascii() => keys::ambiguous_alternative(chr as u8, self.control_character_style),
+                _ => None
+            };
+            return Ok(KeyPress { modifiers, key, is_repeat: false, ambiguous, alternates: None });
         }
     }
 }
@@ -0,0 +1,133 @@
+use crate::{Event, Modifiers};
+use super::csi;
+
+// xterm packs the button number together with flags for the modifiers and for motion. There is no
+// room for Super or the lock keys, and what xterm calls Meta here is the Alt key.
+const SHIFT_FLAG: u32 = 4;
+const ALT_FLAG: u32 = 8;
+const CTRL_FLAG: u32 = 16;
+const MOTION_FLAG: u32 = 32;
+
+// X10-style reports use this button number for every release
+const X10_RELEASE: u32 = 3;
+
+// Translate to the ncurses button state bits that `Event::Mouse` uses. Scrolling shows up as
+// presses of buttons 4 and 5.
+fn button_state(button: u32, released: bool) -> Option<ncurses::ll::mmask_t> {
+    let state = match (button, released) {
+        (0, false) => ncurses::BUTTON1_PRESSED,
+        (0, true) => ncurses::BUTTON1_RELEASED,
+        (1, false) => ncurses::BUTTON2_PRESSED,
+        (1, true) => ncurses::BUTTON2_RELEASED,
+        (2, false) => ncurses::BUTTON3_PRESSED,
+        (2, true) => ncurses::BUTTON3_RELEASED,
+        (64, false) => ncurses::BUTTON4_PRESSED,
+        (65, false) => ncurses::BUTTON5_PRESSED,
+        _ => return None
+    };
+    Some(state as ncurses::ll::mmask_t)
+}
+
+// Build an event from xterm's button code and one-based coordinates
+fn decode_button(code: u32, released: bool, x: u32, y: u32) -> Option<Event> {
+    let buttons = if code & MOTION_FLAG != 0 {
+        ncurses::REPORT_MOUSE_POSITION as ncurses::ll::mmask_t
+    } else {
+        button_state(code & !(SHIFT_FLAG | ALT_FLAG | CTRL_FLAG), released)?
+    };
+    let modifiers = if code & SHIFT_FLAG != 0 { Modifiers::SHIFT } else { Modifiers::NONE }
+                  | if code & ALT_FLAG != 0 { Modifiers::ALT } else { Modifiers::NONE }
+                  | if code & CTRL_FLAG != 0 { Modifiers::CTRL } else { Modifiers::NONE };
+    Some(Event::Mouse {
+        device_id: 0,
+        modifiers,
+        buttons,
+        x: x.checked_sub(1)?,
+        y: y.checked_sub(1)?
+    })
+}
+
+/// Decode an SGR-style mouse report, `CSI < b ; x ; y M` for a press or `CSI < b ; x ; y m` for a
+/// release.
+pub fn decode_sgr(sequence: &csi::ControlSequence) -> Option<Event> {
+    match (&sequence.parameters[..], sequence.final_byte) {
+        (&[code, x, y], b'M') => decode_button(code, false, x, y),
+        (&[code, x, y], b'm') => decode_button(code, true, x, y),
+        _ => None
+    }
+}
+
+/// Decode the three bytes following `CSI M` in an X10-style mouse report, each of which is offset
+/// by 32. Since releases don't say which button was released, this keeps track of the last button
+/// that was pressed in `pressed`.
+pub fn decode_x10(bytes: [u8; 3], pressed: &mut Option<u32>) -> Option<Event> {
+    let code = (bytes[0] as u32).checked_sub(32)?;
+    let x = (bytes[1] as u32).checked_sub(32)?;
+    let y = (bytes[2] as u32).checked_sub(32)?;
+
+    let button = code & !(SHIFT_FLAG | ALT_FLAG | CTRL_FLAG | MOTION_FLAG);
+    if button == X10_RELEASE {
+        let released = pressed.take().unwrap_or(0);
+        decode_button((code & !X10_RELEASE) | released, true, x, y)
+    } else {
+        if code & MOTION_FLAG == 0 && button < X10_RELEASE {
+            *pressed = Some(button);
+        }
+        decode_button(code, false, x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> csi::ControlSequence {
+        let mut bytes = bytes.iter().cloned();
+        csi::parse(|| bytes.next()).unwrap()
+    }
+
+    fn is_mouse(event: Option<Event>, state: ncurses::ll::mmask_t, expected_modifiers: Modifiers, expected_x: u32, expected_y: u32) -> bool {
+        matches!(event, Some(Event::Mouse { modifiers, buttons, x, y, .. })
+            if modifiers == expected_modifiers && buttons == state && x == expected_x && y == expected_y)
+    }
+
+    #[test]
+    fn sgr() {
+        const PRESSED: ncurses::ll::mmask_t = ncurses::BUTTON1_PRESSED as ncurses::ll::mmask_t;
+        const RELEASED: ncurses::ll::mmask_t = ncurses::BUTTON1_RELEASED as ncurses::ll::mmask_t;
+        assert!(is_mouse(decode_sgr(&parse(b"<0;5;3M")), PRESSED, Modifiers::NONE, 4, 2));
+        assert!(is_mouse(decode_sgr(&parse(b"<0;5;3m")), RELEASED, Modifiers::NONE, 4, 2));
+        assert!(is_mouse(decode_sgr(&parse(b"<16;1;1M")), PRESSED, Modifiers::CTRL, 0, 0));
+        assert!(is_mouse(decode_sgr(&parse(b"<12;300;200M")), PRESSED, Modifiers::SHIFT | Modifiers::ALT, 299, 199));
+        assert!(is_mouse(decode_sgr(&parse(b"<64;1;1M")), ncurses::BUTTON4_PRESSED as ncurses::ll::mmask_t, Modifiers::NONE, 0, 0));
+        assert!(is_mouse(decode_sgr(&parse(b"<35;2;2M")), ncurses::REPORT_MOUSE_POSITION as ncurses::ll::mmask_t, Modifiers::NONE, 1, 1));
+    }
+
+    #[test]
+    fn sgr_malformed() {
+        assert!(decode_sgr(&parse(b"<0;0;3M")).is_none());
+        assert!(decode_sgr(&parse(b"<0;5M")).is_none());
+        assert!(decode_sgr(&parse(b"<7;5;3M")).is_none());
+        assert!(decode_sgr(&parse(b"<64;5;3m")).is_none());
+    }
+
+    #[test]
+    fn x10() {
+        let mut pressed = None;
+        let event = decode_x10([32 + 2, 32 + 5, 32 + 3], &mut pressed);
+        assert!(is_mouse(event, ncurses::BUTTON3_PRESSED as ncurses::ll::mmask_t, Modifiers::NONE, 4, 2));
+        assert_eq!(pressed, Some(2));
+
+        // Scrolling doesn't replace the button that will be released
+        let event = decode_x10([32 + 64, 32 + 5, 32 + 3], &mut pressed);
+        assert!(is_mouse(event, ncurses::BUTTON4_PRESSED as ncurses::ll::mmask_t, Modifiers::NONE, 4, 2));
+        assert_eq!(pressed, Some(2));
+
+        let event = decode_x10([32 + 3 + 16, 32 + 6, 32 + 3], &mut pressed);
+        assert!(is_mouse(event, ncurses::BUTTON3_RELEASED as ncurses::ll::mmask_t, Modifiers::CTRL, 5, 2));
+        assert_eq!(pressed, None);
+
+        assert!(decode_x10([31, 32 + 1, 32 + 1], &mut pressed).is_none());
+        assert!(decode_x10([32, 32, 32 + 1], &mut pressed).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Rgb;
+
+/// An OSC (`ESC ]`) operating system command, as sent by the terminal in response to a query.
+pub struct OperatingSystemCommand {
+    pub command: u32,
+    /// Everything after the `;` following the command number, excluding the terminator.
+    pub data: Vec<u8>,
+    /// Whether the data was longer than allowed, in which case the excess was dropped.
+    pub truncated: bool
+}
+
+/// Parse the remainder of an operating system command after the `ESC ]` that introduced it,
+/// pulling bytes from `next` until the terminating BEL or ST (`ESC \`). At most `max_length(command)`
+/// bytes of data are kept. Returns `None` if the input ends early or isn't a well-formed command.
+pub fn parse<F, L>(mut next: F, max_length: L) -> Option<OperatingSystemCommand>
+        where F: FnMut() -> Option<u8>, L: FnOnce(u32) -> usize {
+    let mut command: Option<u32> = None;
+    loop {
+        match next()? {
+            byte @ b'0'..=b'9' => {
+                command = Some(command.unwrap_or(0).checked_mul(10)?.checked_add((byte - b'0') as u32)?);
+            },
+            b';' => break,
+            _ => return None
+        }
+    }
+
+    let command = command?;
+    let (data, truncated) = parse_string(next, max_length(command))?;
+    Some(OperatingSystemCommand { command, data, truncated })
+}
+
+/// Read the data of a control string up to the terminating BEL or ST (`ESC \`), keeping at most
+/// `max_length` bytes. Also returns whether any bytes were dropped.
+pub fn parse_string<F: FnMut() -> Option<u8>>(mut next: F, max_length: usize) -> Option<(Vec<u8>, bool)> {
+    let mut data = Vec::new();
+    let mut truncated = false;
+    loop {
+        match next()? {
+            0x07 => return Some((data, truncated)),
+            0x1b => return if next()? == b'\\' {
+                Some((data, truncated))
+            } else {
+                None
+            },
+            _ if data.len() >= max_length => truncated = true,
+            byte => data.push(byte)
+        }
+    }
+}
+
+/// Decode standard (RFC 4648) base64, ignoring padding.
+pub fn decode_base64(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in encoded.iter().take_while(|&&byte| byte != b'=') {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Decode an X11 color specification of the form `rgb:R/G/B`, where each component has between 1
+/// and 4 hexadecimal digits.
+pub fn parse_rgb(spec: &[u8]) -> Option<Rgb> {
+    if !spec.starts_with(b"rgb:") {
+        return None;
+    }
+
+    let mut components = spec[4..].split(|&byte| byte == b'/').map(|component| {
+        if component.is_empty() || component.len() > 4 {
+            return None;
+        }
+        let mut value = 0u32;
+        for &digit in component {
+            value = value * 16 + (digit as char).to_digit(16)?;
+        }
+        // Scale to 16 bits, so that e.g. `f` and `ffff` both mean full intensity
+        let max = (1u32 << (4 * component.len())) - 1;
+        Some((value * 0xffff / max) as u16)
+    });
+
+    let red = components.next()??;
+    let green = components.next()??;
+    let blue = components.next()??;
+    if components.next().is_some() {
+        return None;
+    }
+    Some(Rgb { red, green, blue })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_base64, parse_rgb};
+    use crate::Rgb;
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64(b""), Some(Vec::new()));
+        assert_eq!(decode_base64(b"aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(decode_base64(b"aGVsbG8h"), Some(b"hello!".to_vec()));
+        assert_eq!(decode_base64(b"aGk="), Some(b"hi".to_vec()));
+        assert_eq!(decode_base64(b"aA=="), Some(b"h".to_vec()));
+        assert_eq!(decode_base64(b"+/+/"), Some(vec![0xfb, 0xff, 0xbf]));
+    }
+
+    #[test]
+    fn base64_without_padding() {
+        assert_eq!(decode_base64(b"aGk"), Some(b"hi".to_vec()));
+        assert_eq!(decode_base64(b"aA"), Some(b"h".to_vec()));
+    }
+
+    #[test]
+    fn malformed_base64() {
+        assert_eq!(decode_base64(b"aGk?"), None);
+        assert_eq!(decode_base64(b"aG k"), None);
+        assert_eq!(decode_base64(b"aGVs\nbG8="), None);
+    }
+
+    #[test]
+    fn rgb() {
+        assert_eq!(parse_rgb(b"rgb:ffff/0000/8080"), Some(Rgb { red: 0xffff, green: 0, blue: 0x8080 }));
+        assert_eq!(parse_rgb(b"rgb:1e1e/1e1e/2e2e"), Some(Rgb { red: 0x1e1e, green: 0x1e1e, blue: 0x2e2e }));
+    }
+
+    #[test]
+    fn rgb_scaling() {
+        // Shorter components are scaled to 16 bits, not zero-extended
+        assert_eq!(parse_rgb(b"rgb:f/0/8"), Some(Rgb { red: 0xffff, green: 0, blue: 0x8888 }));
+        assert_eq!(parse_rgb(b"rgb:ff/80/00"), Some(Rgb { red: 0xffff, green: 0x8080, blue: 0 }));
+        assert_eq!(parse_rgb(b"rgb:fff/800/000"), Some(Rgb { red: 0xffff, green: 0x8007, blue: 0 }));
+    }
+
+    #[test]
+    fn malformed_rgb() {
+        assert_eq!(parse_rgb(b""), None);
+        assert_eq!(parse_rgb(b"#ff0000"), None);
+        assert_eq!(parse_rgb(b"rgba:ffff/0000/0000/ffff"), None);
+        assert_eq!(parse_rgb(b"rgb:ffff/0000"), None);
+        assert_eq!(parse_rgb(b"rgb:ffff/0000/0000/0000"), None);
+        assert_eq!(parse_rgb(b"rgb:fffff/0/0"), None);
+        assert_eq!(parse_rgb(b"rgb:ffff//0000"), None);
+        assert_eq!(parse_rgb(b"rgb:gggg/0000/0000"), None);
+    }
+}
@@ -0,0 +1,176 @@
+/// A set of byte sequences, e.g. the escape sequences sent by special keys, each mapped to a value.
+/// No sequence may be a prefix of another, so a complete match is always unambiguous.
+pub struct SequenceTrie<T> {
+    value: Option<T>,
+    children: Vec<(u8, SequenceTrie<T>)>
+}
+
+/// How far a sequence of bytes matches an entry in a `SequenceTrie`.
+pub enum Match<'a, T> {
+    Complete(&'a T),
+    /// The bytes are the start of at least one sequence, so more input is needed to decide.
+    Partial,
+    None
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub struct PrefixConflict;
+
+impl<T> SequenceTrie<T> {
+    pub fn new() -> Self {
+        SequenceTrie {
+            value: None,
+            children: Vec::new()
+        }
+    }
+
+    fn child(&self, byte: u8) -> Option<&SequenceTrie<T>> {
+        self.children.iter().find(|&&(child_byte, _)| child_byte == byte).map(|(_, child)| child)
+    }
+
+    /// Map `sequence` to `value`, replacing any previous value for exactly the same sequence. Fails
+    /// if the sequence is empty, is a prefix of an existing sequence, or has one as a prefix.
+    pub fn insert(&mut self, sequence: &[u8], value: T) -> Result<(), PrefixConflict> {
+        if self.value.is_some() {
+            return Err(PrefixConflict);
+        }
+        let (&first, rest) = match sequence.split_first() {
+            Some(split) => split,
+            None => return Err(PrefixConflict)
+        };
+
+        let index = match self.children.iter().position(|&(byte, _)| byte == first) {
+            Some(index) => index,
+            None => {
+                self.children.push((first, SequenceTrie::new()));
+                self.children.len() - 1
+            }
+        };
+        let child = &mut self.children[index].1;
+        let result = if rest.is_empty() {
+            if child.children.is_empty() {
+                child.value = Some(value);
+                Ok(())
+            } else {
+                Err(PrefixConflict)
+            }
+        } else {
+            child.insert(rest, value)
+        };
+
+        // Don't leave behind an empty branch for a failed insertion
+        if self.children[index].1.value.is_none() && self.children[index].1.children.is_empty() {
+            self.children.remove(index);
+        }
+        result
+    }
+
+    /// Remove `sequence` and return its value, if it was present.
+    pub fn remove(&mut self, sequence: &[u8]) -> Option<T> {
+        let (&first, rest) = match sequence.split_first() {
+            Some(split) => split,
+            None => return self.value.take()
+        };
+        let index = self.children.iter().position(|&(byte, _)| byte == first)?;
+        let value = self.children[index].1.remove(rest);
+        if self.children[index].1.value.is_none() && self.children[index].1.children.is_empty() {
+            self.children.remove(index);
+        }
+        value
+    }
+
+    pub fn find(&self, sequence: &[u8]) -> Match<'_, T> {
+        let mut node = self;
+        for &byte in sequence {
+            node = match node.child(byte) {
+                Some(child) => child,
+                None => return Match::None
+            };
+        }
+        match node.value {
+            Some(ref value) => Match::Complete(value),
+            None if node.children.is_empty() => Match::None,
+            None => Match::Partial
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Match, PrefixConflict, SequenceTrie};
+
+    #[test]
+    fn find() {
+        let mut trie = SequenceTrie::new();
+        trie.insert(b"\x1b[A", 1).unwrap();
+        trie.insert(b"\x1bOA", 2).unwrap();
+        assert!(matches!(trie.find(b"\x1b[A"), Match::Complete(&1)));
+        assert!(matches!(trie.find(b"\x1bOA"), Match::Complete(&2)));
+        assert!(matches!(trie.find(b"\x1b"), Match::Partial));
+        assert!(matches!(trie.find(b"\x1b["), Match::Partial));
+        assert!(matches!(trie.find(b"\x1b[B"), Match::None));
+        assert!(matches!(trie.find(b"\x1b[A~"), Match::None));
+    }
+
+    #[test]
+    fn null_bytes() {
+        let mut trie = SequenceTrie::new();
+        trie.insert(b"\x1b\x00", 1).unwrap();
+        trie.insert(b"\x00", 2).unwrap();
+        assert!(matches!(trie.find(b"\x1b\x00"), Match::Complete(&1)));
+        assert!(matches!(trie.find(b"\x00"), Match::Complete(&2)));
+    }
+
+    #[test]
+    fn replace() {
+        let mut trie = SequenceTrie::new();
+        trie.insert(b"\x1b[A", 1).unwrap();
+        trie.insert(b"\x1b[A", 2).unwrap();
+        assert!(matches!(trie.find(b"\x1b[A"), Match::Complete(&2)));
+    }
+
+    #[test]
+    fn prefix_conflicts() {
+        let mut trie = SequenceTrie::new();
+        assert_eq!(trie.insert(b"", 0), Err(PrefixConflict));
+        trie.insert(b"\x1b[1;5A", 1).unwrap();
+        assert_eq!(trie.insert(b"\x1b[1", 2), Err(PrefixConflict));
+        assert_eq!(trie.insert(b"\x1b[1;5A~", 3), Err(PrefixConflict));
+        assert!(matches!(trie.find(b"\x1b[1"), Match::Partial));
+        assert!(matches!(trie.find(b"\x1b[1;5A"), Match::Complete(&1)));
+    }
+
+    #[test]
+    fn failed_insert_leaves_no_branch() {
+        let mut trie = SequenceTrie::new();
+        trie.insert(b"ab", 1).unwrap();
+        assert_eq!(trie.insert(b"abcd", 2), Err(PrefixConflict));
+        trie.insert(b"xy", 3).unwrap();
+        assert_eq!(trie.remove(b"ab"), Some(1));
+        assert_eq!(trie.remove(b"xy"), Some(3));
+        assert!(matches!(trie.find(b"a"), Match::None));
+        assert!(matches!(trie.find(b"x"), Match::None));
+    }
+
+    #[test]
+    fn remove() {
+        let mut trie = SequenceTrie::new();
+        trie.insert(b"abc", 1).unwrap();
+        trie.insert(b"abd", 2).unwrap();
+        assert_eq!(trie.remove(b"abc"), Some(1));
+        assert_eq!(trie.remove(b"abc"), None);
+        assert!(matches!(trie.find(b"abc"), Match::None));
+        assert!(matches!(trie.find(b"abd"), Match::Complete(&2)));
+        assert!(matches!(trie.find(b"ab"), Match::Partial));
+
+        // An interior node has no value to remove and must keep its children
+        assert_eq!(trie.remove(b"ab"), None);
+        assert!(matches!(trie.find(b"abd"), Match::Complete(&2)));
+
+        // Removing the last sequence through a node prunes it, so its prefixes can be bound
+        assert_eq!(trie.remove(b"abd"), Some(2));
+        assert!(matches!(trie.find(b"a"), Match::None));
+        trie.insert(b"ab", 3).unwrap();
+        assert!(matches!(trie.find(b"ab"), Match::Complete(&3)));
+    }
+}
@@ -1,14 +1,16 @@
 extern crate ncurses;
-#[macro_use] extern crate const_cstr;
 
 use core::ops::{BitOr, BitAnd};
 use core::convert::TryInto;
+use core::fmt;
+use core::str::FromStr;
 
 mod imp_ncurses;
 
-/// The set of modifier keys (e.g. Ctrl, Alt, and Shift) that were pressed at the time of an event.
-/// Represented as an opaque bitmap to allow for extension with other keys, such as a Meta or
-/// Command key.
+/// The set of modifier keys (e.g. Ctrl, Alt, and Shift) that were pressed at the time of an event,
+/// along with the state of the lock keys. Represented as an opaque bitmap, laid out like the
+/// modifiers in xterm's and kitty's key encodings. Most terminals can only report Shift, Alt, and
+/// Ctrl.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Modifiers(u8);
 
@@ -34,6 +36,15 @@ impl Modifiers {
     pub const SHIFT: Modifiers = Modifiers(0b1);
     pub const ALT: Modifiers = Modifiers(0b10);
     pub const CTRL: Modifiers = Modifiers(0b100);
+    /// The Windows or Command key. xterm calls this Meta.
+    pub const SUPER: Modifiers = Modifiers(0b1000);
+    pub const HYPER: Modifiers = Modifiers(0b10000);
+    pub const META: Modifiers = Modifiers(0b100000);
+    pub const CAPS_LOCK: Modifiers = Modifiers(0b1000000);
+    pub const NUM_LOCK: Modifiers = Modifiers(0b10000000);
+
+    /// Both lock keys, which terminals that report them at all include with every key.
+    pub const LOCKS: Modifiers = Modifiers(Modifiers::CAPS_LOCK.0 | Modifiers::NUM_LOCK.0);
 
     pub const fn remove(self, other: Modifiers) -> Modifiers {
         Modifiers(self.0 & !other.0)
@@ -51,6 +62,120 @@ impl Modifiers {
     pub const fn eq(&self, other: &Modifiers) -> bool {
         self.0 == other.0
     }
+
+    /// The same modifiers without Caps Lock and Num Lock.
+    pub const fn without_locks(self) -> Modifiers {
+        self.remove(Modifiers::LOCKS)
+    }
+
+    /// Compare modifiers while ignoring the lock keys, e.g. to check for a shortcut like Ctrl+S
+    /// regardless of whether Caps Lock is on.
+    pub const fn eq_ignoring_locks(&self, other: &Modifiers) -> bool {
+        self.0 & !Modifiers::LOCKS.0 == other.0 & !Modifiers::LOCKS.0
+    }
+
+    /// Whether every modifier in `other` is also in `self`.
+    pub const fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether any modifier in `other` is also in `self`.
+    pub const fn intersects(self, other: Modifiers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn insert(&mut self, other: Modifiers) {
+        self.0 |= other.0;
+    }
+
+    /// Each modifier that is set, one at a time, in the order that `Display` uses.
+    pub fn iter(self) -> impl Iterator<Item=Modifiers> {
+        MODIFIER_NAMES.iter().map(|&(modifier, _)| modifier).filter(move |&modifier| self.contains(modifier))
+    }
+
+    /// Decode the modifier parameter used by xterm and kitty, which is one more than the bitmap.
+    pub fn from_xterm_param(parameter: u32) -> Option<Modifiers> {
+        if (1..=256).contains(&parameter) {
+            Some(Modifiers((parameter - 1) as u8))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_xterm_param(self) -> u32 {
+        self.0 as u32 + 1
+    }
+}
+
+// In the conventional order for writing shortcuts
+const MODIFIER_NAMES: [(Modifiers, &str); 8] = [
+    (Modifiers::CTRL, "Ctrl"),
+    (Modifiers::ALT, "Alt"),
+    (Modifiers::SHIFT, "Shift"),
+    (Modifiers::SUPER, "Super"),
+    (Modifiers::HYPER, "Hyper"),
+    (Modifiers::META, "Meta"),
+    (Modifiers::CAPS_LOCK, "CapsLock"),
+    (Modifiers::NUM_LOCK, "NumLock"),
+];
+
+/// Writes the modifiers joined by `+`, as in `Ctrl+Alt+Shift`. No modifiers at all is written as
+/// an empty string.
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, modifier) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str("+")?;
+            }
+            let &(_, name) = MODIFIER_NAMES.iter().find(|&&(named, _)| named == modifier).unwrap();
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+/// The error returned when parsing `Modifiers` from a string with something other than a modifier
+/// name in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseModifiersError {
+    pub unknown: String
+}
+
+impl fmt::Display for ParseModifiersError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown modifier `{}`", self.unknown)
+    }
+}
+
+impl std::error::Error for ParseModifiersError { }
+
+/// Parses the format written by `Display`, ignoring case and whitespace around the names. `Control`
+/// is also accepted for Ctrl.
+impl FromStr for Modifiers {
+    type Err = ParseModifiersError;
+
+    fn from_str(string: &str) -> Result<Modifiers, ParseModifiersError> {
+        let mut modifiers = Modifiers::NONE;
+        if string.trim().is_empty() {
+            return Ok(modifiers);
+        }
+        for name in string.split('+').map(str::trim) {
+            let modifier = if name.eq_ignore_ascii_case("control") {
+                Some(Modifiers::CTRL)
+            } else {
+                MODIFIER_NAMES.iter().find(|&&(_, known)| known.eq_ignore_ascii_case(name)).map(|&(modifier, _)| modifier)
+            };
+            match modifier {
+                Some(modifier) => modifiers.insert(modifier),
+                None => return Err(ParseModifiersError { unknown: name.to_string() })
+            }
+        }
+        Ok(modifiers)
+    }
 }
 
 /// A single event generated by a terminal. Simple text input, whether arriving via a pipe, a
@@ -59,7 +184,7 @@ impl Modifiers {
 /// certain modifier keys may just never be recorded, key repeats will be indistinguishable from
 /// orignal presses, pastes may not be bracketed, and key releases may never be registered, among
 /// other failures.
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     /// A single typing action by the user, input from stdin. Except between PasteBegin and PasteEnd
     /// events, these typically will not be control characters, as those are heuristically decoded
@@ -69,12 +194,20 @@ pub enum Event {
         key: KeyInput,
         /// Whether this keypress comes from holding down a key
         is_repeat: bool,
+        /// Another key press that the terminal sends in exactly the same way, if any, e.g. Ctrl+I
+        /// for Tab. Applications can treat the event as either one, e.g. when looking up bindings.
+        ambiguous: Option<(Modifiers, KeyInput)>,
+        /// Other names for the key, from terminals that know the keyboard layout. With Shift held,
+        /// these terminals report the shifted key without Shift, e.g. Ctrl+`?` for Ctrl+Shift+/ on
+        /// a US layout.
+        alternates: Option<AlternateKeys>,
     },
     /// This is kept as a separate event from KeyPress as it usually does not want to be handled in
     /// the same way and is supported by very few terminals, making it easy to miss in testing.
     KeyRelease {
         modifiers: Modifiers,
         key: KeyInput,
+        alternates: Option<AlternateKeys>,
     },
     /// A motion or click of a mouse button. Modifiers typically are only be available on button
     /// state changes, not mouse motion.
@@ -92,11 +225,129 @@ pub enum Event {
     PasteBegin,
     /// The marker indicating a return to normal user interaction.
     PasteEnd,
+    /// The terminal window has gained focus. Applications may want to check whether anything
+    /// changed while the user was away.
+    FocusGained,
+    /// The terminal window has lost focus.
+    FocusLost,
     /// The window has been resized and the application may want to rerender to fit the new sizee.
+    /// The width and height are measured in cells. The size in pixels is only available from
+    /// terminals that support in-band resize notifications.
     Resize {
         width: u32,
-        height: u32
-    }
+        height: u32,
+        pixel_width: Option<u32>,
+        pixel_height: Option<u32>
+    },
+    /// The position of the cursor, as requested by `InputStream::request_cursor_position`. Both
+    /// coordinates are zero-based, like those of `Mouse` events.
+    CursorPosition {
+        row: u32,
+        col: u32
+    },
+    /// The terminal switched between a dark and a light color scheme, or is answering
+    /// `InputStream::request_color_scheme`.
+    ColorSchemeChanged {
+        dark: bool
+    },
+    /// The answer to `InputStream::request_color`.
+    ColorReport {
+        color: TerminalColor,
+        rgb: Rgb
+    },
+    /// The contents of the system clipboard, as requested by `InputStream::request_clipboard`, or
+    /// the reason they couldn't be delivered. Like a paste, this should be treated as untrusted
+    /// text. Invalid UTF-8 is replaced with U+FFFD.
+    Clipboard(Result<String, ClipboardError>),
+    /// The answer to `InputStream::request_terminal_capability`. The value is `None` if the terminal
+    /// does not know the capability.
+    TerminalCapability {
+        name: String,
+        value: Option<Vec<u8>>
+    },
+    /// A complete control sequence or control string (CSI, SS3, OSC, DCS, or APC) that was not
+    /// recognized, including the leading escape. These usually come from keys or terminal features
+    /// that terminal-input does not know about and are generally safe to ignore.
+    UnknownSequence(Vec<u8>)
+}
+
+/// The keys that a key event could also be described as, which only terminals that know the
+/// keyboard layout can report.
+#[derive(Copy, Clone, Debug)]
+pub struct AlternateKeys {
+    /// The key without Shift, e.g. `/` when the event's key is `?`. This is the same as the event's
+    /// key when Shift isn't held.
+    pub unshifted: KeyInput,
+    /// The key in the same position on a standard US layout, if that is different, e.g. `c` for
+    /// the Cyrillic `с`. This lets shortcuts work the same way regardless of the layout.
+    pub base_layout: Option<KeyInput>,
+}
+
+/// One of the colors that the terminal uses to draw text, which can be queried with
+/// `InputStream::request_color`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TerminalColor {
+    /// The default text color
+    Foreground,
+    /// The default background color
+    Background,
+    /// An entry in the terminal's 256-color palette
+    Palette(u8)
+}
+
+/// A color reported by the terminal, with 16 bits per channel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16
+}
+
+/// The reason that the terminal's answer to `InputStream::request_clipboard` could not be delivered.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClipboardError {
+    /// The contents were longer than the maximum length that was asked for.
+    TooLarge,
+    /// The answer wasn't in the expected format, e.g. because it wasn't valid base64.
+    Malformed
+}
+
+/// How to report the control characters that don't stand for a letter, `0x00` and `0x1C` through
+/// `0x1F`, since terminals send each of them for several key combinations. This can be chosen
+/// with `InputStream::set_control_character_style`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControlCharacterStyle {
+    /// Ctrl+Space, then Ctrl+\\, Ctrl+], Ctrl+^, and Ctrl+_ as in caret notation. This is the
+    /// default.
+    Caret,
+    /// The keys that type them on a US layout: Ctrl+Space, Ctrl+\\, Ctrl+], Ctrl+Shift+6, and
+    /// Ctrl+/.
+    UsLayout,
+    /// xterm's digit aliases: Ctrl+2, Ctrl+4, Ctrl+5, Ctrl+6, and Ctrl+7.
+    Digits,
+}
+
+/// How to read input bytes with the high bit set, which can mean either of two things depending on
+/// the terminal's settings. This can be chosen with `InputStream::set_high_bit_input`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HighBitInput {
+    /// As part of UTF-8 encoded characters, with bytes that aren't valid UTF-8 reported as
+    /// `KeyInput::Byte`. This is the default. The C1 control characters U+0080 through U+009F are
+    /// the exception, as no key types them: they are reported like a control character with its
+    /// high bit set in `Meta`, since that is what xterm sends for Alt in 8-bit meta mode when it
+    /// encodes its input as UTF-8.
+    Utf8,
+    /// As Alt plus the byte without its high bit, which terminals in 8-bit meta mode send instead
+    /// of an escape, e.g. xterm with `metaSendsEscape` off or urxvt with `meta8`.
+    Meta,
+}
+
+/// The reason that `InputStream::bind_sequence` could not bind a sequence.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BindError {
+    /// The sequence starts with a sequence that is already bound or is itself the start of one, so
+    /// one of the two could never be recognized. The empty sequence conflicts with everything.
+    PrefixConflict
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -106,6 +357,46 @@ pub enum KeyInput {
     Byte(u8),
     /// A key not inputting a printable character.
     Special(i32),
+    /// A key on the numeric keypad, given as the character that it types: a digit, one of
+    /// `.,/*-+=`, or `\n` for the keypad's Enter key. When NumLock is off, the digit keys are
+    /// reported as the editing keys that they double as instead, e.g. `Special(KEY_HOME)` for 7.
+    Keypad(char),
+    /// The modifier keys themselves, which only terminals reporting every key press and release
+    /// send on their own. While a modifier key is held, its flag is usually also reported in the
+    /// event's `Modifiers`, but terminals differ in whether that is already true for the press.
+    LeftShift,
+    RightShift,
+    LeftCtrl,
+    RightCtrl,
+    LeftAlt,
+    RightAlt,
+    LeftSuper,
+    RightSuper,
+    CapsLock,
+    ScrollLock,
+    NumLock,
+    PrintScreen,
+    Pause,
+    Menu,
+    /// Media and volume keys, which only terminals reporting every key send.
+    Media(MediaKey),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MediaKey {
+    Play,
+    Pause,
+    PlayPause,
+    Reverse,
+    Stop,
+    FastForward,
+    Rewind,
+    NextTrack,
+    PreviousTrack,
+    Record,
+    VolumeDown,
+    VolumeUp,
+    Mute,
 }
 
 pub struct InputStream<'a> {
@@ -116,6 +407,10 @@ pub struct InputStream<'a> {
 }
 
 impl<'a> InputStream<'a> {
+    /// # Safety
+    ///
+    /// `screen` must be the window returned by `ncurses::initscr`, and ncurses must stay
+    /// initialized for as long as the returned `InputStream` is in use.
     pub unsafe fn init_with_ncurses(data: std::io::StdinLock<'a>, screen: ncurses::ll::WINDOW) -> InputStream<'a> {
         InputStream {
             inner: imp_ncurses::InputStream::init(screen),
@@ -125,17 +420,160 @@ impl<'a> InputStream<'a> {
     }
 
     // Wait until a new event is received. Note that the `Err` case should not generally be fatal;
-    // this can be generated in some cases by inputs that terminal-input or ncurses is confused by.
-    // In testing, this tends to happen when scrolling sideways on xterm, for example.
+    // this can be generated in some cases when reading from the terminal is interrupted.
+    #[allow(clippy::result_unit_err)]
     pub fn next_event(&mut self) -> Result<Event, ()> {
         self.inner.next_event(self.screen)
     }
 
+    // Switch the cursor keys between sending `CSI` sequences (the default) and `SS3` sequences
+    // (DECCKM). Both are recognized either way, so this only matters to the terminal's other users.
+    pub fn set_application_cursor_keys(&mut self, enabled: bool) -> Result<(), std::io::Error> {
+        self.inner.set_application_cursor_keys(enabled)
+    }
+
+    // Switch the numeric keypad between application mode (DECKPAM, the default), in which its keys
+    // are reported as `KeyInput::Keypad`, and numeric mode, in which they look like the main
+    // keyboard.
+    pub fn set_application_keypad(&mut self, enabled: bool) -> Result<(), std::io::Error> {
+        self.inner.set_application_keypad(enabled)
+    }
+
+    // Send the cursor key and keypad modes to the terminal again, e.g. after running another
+    // program or calling `endwin`, either of which may have changed them.
+    pub fn restore_key_modes(&mut self) -> Result<(), std::io::Error> {
+        self.inner.restore_key_modes()
+    }
+
+    // Ask the terminal where the cursor currently is. The answer will arrive later as a
+    // `CursorPosition` event.
+    pub fn request_cursor_position(&mut self) -> Result<(), std::io::Error> {
+        self.inner.request_cursor_position()
+    }
+
+    // Ask the terminal to send `ColorSchemeChanged` events whenever the user switches between dark
+    // and light themes. Terminals that do not support this will silently ignore the request.
+    pub fn enable_color_scheme_notifications(&mut self) -> Result<(), std::io::Error> {
+        self.inner.enable_color_scheme_notifications()
+    }
+
+    // Ask the terminal whether it is currently using a dark or light theme. The answer will arrive
+    // later as a `ColorSchemeChanged` event, if the terminal supports the query at all.
+    pub fn request_color_scheme(&mut self) -> Result<(), std::io::Error> {
+        self.inner.request_color_scheme()
+    }
+
+    // Ask the terminal what RGB value it uses for one of its colors. The answer will arrive later
+    // as a `ColorReport` event, if the terminal supports the query at all.
+    pub fn request_color(&mut self, color: TerminalColor) -> Result<(), std::io::Error> {
+        self.inner.request_color(color)
+    }
+
+    // Ask the terminal for the contents of the system clipboard, which will arrive later as a
+    // `Clipboard` event. This works over SSH, but many terminals disable it by default or ask the
+    // user for permission first. Clipboard contents longer than `max_length` bytes are discarded
+    // and reported as `ClipboardError::TooLarge`.
+    pub fn request_clipboard(&mut self, max_length: usize) -> Result<(), std::io::Error> {
+        self.inner.request_clipboard(max_length)
+    }
+
+    // Ask the terminal itself for the value of a terminfo capability (XTGETTCAP), which can be more
+    // accurate than the local terminfo database, e.g. over SSH. The answer will arrive later as a
+    // `TerminalCapability` event, if the terminal supports the query at all.
+    pub fn request_terminal_capability(&mut self, name: &str) -> Result<(), std::io::Error> {
+        self.inner.request_terminal_capability(name)
+    }
+
+    // Ask the terminal how it encodes modified keys (XTGETTCAP) and recognize those sequences as
+    // the answers arrive, in addition to the ones from the local terminfo database. This fixes
+    // modified keys when the local terminfo is missing or wrong, e.g. over SSH.
+    pub fn request_key_capabilities(&mut self) -> Result<(), std::io::Error> {
+        self.inner.request_key_capabilities()
+    }
+
+    // Recognize `sequence` as `event` from now on, e.g. for a nonstandard key or a keyboard macro.
+    // This replaces any existing meaning of exactly the same sequence, including the built-in
+    // ones, though terminal reports such as mouse events are still decoded first.
+    pub fn bind_sequence(&mut self, sequence: &[u8], event: Event) -> Result<(), BindError> {
+        self.inner.bind_sequence(sequence, event)
+    }
+
+    // Remove the binding for `sequence`, returning the event it was bound to, if any. This works
+    // for the built-in keys as well, though sequences following xterm's standard key encoding are
+    // still decoded as such.
+    pub fn unbind_sequence(&mut self, sequence: &[u8]) -> Option<Event> {
+        self.inner.unbind_sequence(sequence)
+    }
+
+    // Choose which key combinations to report the control characters without a letter of their
+    // own as, e.g. `0x1F` as Ctrl+_, Ctrl+/, or Ctrl+7.
+    pub fn set_control_character_style(&mut self, style: ControlCharacterStyle) {
+        self.inner.set_control_character_style(style);
+    }
+
+    // Choose whether bytes with the high bit set are UTF-8 or Alt plus another key. Switching to
+    // `HighBitInput::Meta` also asks the terminal to send Alt that way, if terminfo says how.
+    pub fn set_high_bit_input(&mut self, high_bit_input: HighBitInput) -> Result<(), std::io::Error> {
+        self.inner.set_high_bit_input(high_bit_input)
+    }
+
     // Set the time delay after an escape character is received to distinguish between the escape
     // key and automatic escape sequences.
     pub fn set_escdelay(&mut self, escdelay: core::time::Duration) {
-        unsafe {
-            ncurses::ll::set_escdelay(escdelay.as_millis().try_into().unwrap_or(i32::MAX));
+        self.inner.set_escape_delay(escdelay.as_millis().try_into().unwrap_or(i32::MAX));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Modifiers, ParseModifiersError};
+
+    #[test]
+    fn display() {
+        assert_eq!(Modifiers::NONE.to_string(), "");
+        assert_eq!(Modifiers::SHIFT.to_string(), "Shift");
+        assert_eq!((Modifiers::SHIFT | Modifiers::CTRL | Modifiers::ALT).to_string(), "Ctrl+Alt+Shift");
+        assert_eq!((Modifiers::SUPER | Modifiers::CAPS_LOCK).to_string(), "Super+CapsLock");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("".parse(), Ok(Modifiers::NONE));
+        assert_eq!("Ctrl+Alt".parse(), Ok(Modifiers::CTRL | Modifiers::ALT));
+        assert_eq!(" control + SHIFT ".parse(), Ok(Modifiers::CTRL | Modifiers::SHIFT));
+        assert_eq!("Ctrl+Foo".parse::<Modifiers>(), Err(ParseModifiersError { unknown: "Foo".to_string() }));
+        assert_eq!("Ctrl+".parse::<Modifiers>(), Err(ParseModifiersError { unknown: "".to_string() }));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for bits in 0..=255 {
+            let modifiers = Modifiers(bits);
+            assert_eq!(modifiers.to_string().parse(), Ok(modifiers));
+        }
+    }
+
+    #[test]
+    fn xterm_param() {
+        assert_eq!(Modifiers::from_xterm_param(0), None);
+        assert_eq!(Modifiers::from_xterm_param(1), Some(Modifiers::NONE));
+        assert_eq!(Modifiers::from_xterm_param(5), Some(Modifiers::CTRL));
+        assert_eq!(Modifiers::from_xterm_param(8), Some(Modifiers::CTRL | Modifiers::ALT | Modifiers::SHIFT));
+        assert_eq!(Modifiers::from_xterm_param(256), Some(Modifiers(255)));
+        assert_eq!(Modifiers::from_xterm_param(257), None);
+        for parameter in 1..=256 {
+            assert_eq!(Modifiers::from_xterm_param(parameter).unwrap().to_xterm_param(), parameter);
         }
     }
+
+    #[test]
+    fn set_operations() {
+        let modifiers = Modifiers::CTRL | Modifiers::SHIFT;
+        assert!(modifiers.contains(Modifiers::CTRL));
+        assert!(!modifiers.contains(Modifiers::CTRL | Modifiers::ALT));
+        assert!(modifiers.intersects(Modifiers::CTRL | Modifiers::ALT));
+        assert!(Modifiers::NONE.is_empty());
+        assert_eq!(modifiers.remove(Modifiers::SHIFT), Modifiers::CTRL);
+        assert_eq!(modifiers.iter().collect::<Vec<_>>(), [Modifiers::CTRL, Modifiers::SHIFT]);
+    }
 }